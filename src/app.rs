@@ -1,24 +1,55 @@
-use crate::state::State;
-use crate::{HEIGHT, WIDTH};
+use crate::PAN_THRESHOLD;
+use crate::config::Config;
+use crate::state::{DebugOverlay, MAX_SHAPE_POINTS, Shape, ShapeKind, State, ToolPreview};
 use std::sync::Arc;
 use winit::application::ApplicationHandler;
-use winit::event::{ElementState, MouseButton, WindowEvent};
+use winit::event::{ElementState, MouseButton, MouseScrollDelta, WindowEvent};
 use winit::event_loop::ActiveEventLoop;
-use winit::keyboard::{Key, NamedKey};
+use winit::keyboard::{Key, ModifiersState, NamedKey};
 use winit::window::{Window, WindowId};
 
+/// What the left mouse button does
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+enum Tool {
+    #[default]
+    Brush,
+    /// Fills the region of equal material under the cursor
+    Fill,
+    /// Rectangles and lines are dragged, polygons are clicked point by point and finished with enter
+    Shape(ShapeKind),
+    /// Drags a rectangle that can be copied, cut or saved as a stamp
+    Select,
+    /// Pastes the clipboard at the cursor, it can be rotated and flipped before
+    Paste,
+}
+
+#[derive(Default)]
 pub(crate) struct App {
+    // Settings the window and the simulation are created with
+    config: Config,
     state: Option<State>,
+    tool: Tool,
+    // Shape being drawn with a shape tool, previewed until it is finished
+    shape: Option<Shape>,
+    // Corners of the selected rectangle, the second one follows the cursor while selecting
+    selection: Option<((i32, i32), (i32, i32))>,
+    selecting: bool,
     cursor_position: Option<(f64, f64)>,
     left_mouse_button_pressed: bool,
+    right_mouse_button_pressed: bool,
+    // Grid position painted last while the button is held, strokes continue from here
+    last_paint_position: Option<(i32, i32)>,
+    // Where the middle mouse button was pressed, dragging it pans the camera and clicking it picks a material
+    middle_mouse_button_pressed_at: Option<(f64, f64)>,
+    panned: bool,
+    modifiers: ModifiersState,
 }
 
-impl Default for App {
-    fn default() -> Self {
+impl App {
+    pub(crate) fn new(config: Config) -> Self {
         Self {
-            state: None,
-            cursor_position: None,
-            left_mouse_button_pressed: false,
+            config,
+            ..Self::default()
         }
     }
 }
@@ -32,13 +63,13 @@ impl ApplicationHandler for App {
         let window = match event_loop.create_window(window_attributes) {
             Ok(window) => Arc::new(window),
             Err(e) => {
-                log::error!("Creating window failed: {}", e.to_string());
+                log::error!("Creating window failed: {}", e);
                 event_loop.exit();
                 return;
             }
         };
 
-        self.state = match pollster::block_on(State::new(window, WIDTH, HEIGHT)) {
+        self.state = match pollster::block_on(State::new(window, &self.config)) {
             Ok(state) => Some(state),
             Err(e) => {
                 log::error!("Failed to create state: {}", e);
@@ -62,6 +93,7 @@ impl ApplicationHandler for App {
             log::warn!("Window event id does not match the window id");
             return;
         }
+        let grid_size = state.grid_size();
 
         match event {
             WindowEvent::CloseRequested => {
@@ -70,41 +102,296 @@ impl ApplicationHandler for App {
             }
             WindowEvent::Resized(size) => state.resize(size.width, size.height),
             WindowEvent::CursorMoved { position, .. } => {
+                // Drag the grid along with the cursor, once it moved far enough to not be a click
+                if let (Some(pressed_at), Some((x, y))) =
+                    (self.middle_mouse_button_pressed_at, self.cursor_position)
+                {
+                    self.panned |= (position.x - pressed_at.0).abs() > PAN_THRESHOLD
+                        || (position.y - pressed_at.1).abs() > PAN_THRESHOLD;
+                    if self.panned {
+                        state.pan_camera(x - position.x, y - position.y);
+                    }
+                }
+
                 self.cursor_position = Some((position.x, position.y));
                 // Update mouse position buffer in GPU
-                state.update_mouse_position(position.x, position.y);
+                state.update_mouse_position(self.cursor_position);
+
+                // The last point of the shape follows the cursor
+                if let Some(shape) = &mut self.shape
+                    && let Some(last) = shape.points.last_mut()
+                {
+                    *last = state.cursor_to_grid(position.x, position.y);
+                    state.update_tool_preview(ToolPreview::Shape(shape));
+                }
+
+                if self.selecting
+                    && let Some((start, _)) = self.selection
+                {
+                    let corner = state.cursor_to_grid(position.x, position.y);
+                    self.selection = Some((start, corner));
+                    state.update_tool_preview(ToolPreview::Selection(start, corner));
+                }
             }
             WindowEvent::CursorLeft { .. } => {
                 self.cursor_position = None;
-                // Set mouse position outside viewport to hide the circle
-                state.update_mouse_position(-1.0, -1.0);
+                self.last_paint_position = None;
+                // Hide the brush preview
+                state.update_mouse_position(None);
             }
             WindowEvent::MouseInput {
                 state: element_state,
-                button: MouseButton::Left,
+                button,
                 ..
             } => {
-                self.left_mouse_button_pressed = element_state == ElementState::Pressed;
+                let pressed = element_state == ElementState::Pressed;
+                let was_painting =
+                    self.left_mouse_button_pressed || self.right_mouse_button_pressed;
+
+                // Clicking a swatch of the palette bar selects its material instead of using the tool
+                let swatch = self
+                    .cursor_position
+                    .and_then(|(x, y)| state.palette_swatch_at(x, y));
+
+                match button {
+                    MouseButton::Left if pressed && let Some(material) = swatch => {
+                        state.select_material(material)
+                    }
+                    MouseButton::Left => match self.tool {
+                        Tool::Brush => self.left_mouse_button_pressed = pressed,
+                        Tool::Fill => {
+                            if pressed && let Some((x, y)) = self.cursor_position {
+                                state.fill(state.cursor_to_grid(x, y));
+                            }
+                        }
+                        Tool::Shape(kind) => {
+                            if !pressed {
+                                // Rectangles and lines are finished at their last point when the button is
+                                // released, even if the cursor has left the window since
+                                if kind != ShapeKind::Polygon
+                                    && let Some(shape) = self.shape.take()
+                                {
+                                    state.draw_shape(shape);
+                                }
+                            } else if let Some((x, y)) = self.cursor_position {
+                                let position = state.cursor_to_grid(x, y);
+                                match &mut self.shape {
+                                    // Start a new shape
+                                    None => self.shape = Some(Shape::new(kind, position)),
+                                    // Fix the current point of the polygon and start the next one
+                                    Some(shape)
+                                        if kind == ShapeKind::Polygon
+                                            && shape.points.len() < MAX_SHAPE_POINTS =>
+                                    {
+                                        shape.points.push(position)
+                                    }
+                                    _ => {}
+                                }
+                            }
+                            update_tool_preview(
+                                self.tool,
+                                self.shape.as_ref(),
+                                self.selection,
+                                state,
+                            );
+                        }
+                        Tool::Select => {
+                            if pressed && let Some((x, y)) = self.cursor_position {
+                                let position = state.cursor_to_grid(x, y);
+                                self.selection = Some((position, position));
+                            }
+                            self.selecting = pressed;
+                            update_tool_preview(self.tool, None, self.selection, state);
+                        }
+                        Tool::Paste => {
+                            if pressed && let Some((x, y)) = self.cursor_position {
+                                state.paste(state.cursor_to_grid(x, y));
+                            }
+                        }
+                    },
+                    MouseButton::Right => self.right_mouse_button_pressed = pressed,
+                    MouseButton::Middle if pressed => {
+                        self.middle_mouse_button_pressed_at = self.cursor_position;
+                        self.panned = false;
+                    }
+                    // Eyedropper: clicking without dragging selects the material under the cursor
+                    MouseButton::Middle => {
+                        if !self.panned
+                            && self.middle_mouse_button_pressed_at.is_some()
+                            && let Some((x, y)) = self.cursor_position
+                        {
+                            state.pick_material(state.cursor_to_grid(x, y));
+                        }
+                        self.middle_mouse_button_pressed_at = None;
+                    }
+                    _ => {}
+                }
+
+                // The stroke ends once both painting buttons are released
+                if was_painting
+                    && !self.left_mouse_button_pressed
+                    && !self.right_mouse_button_pressed
+                {
+                    self.last_paint_position = None;
+                    state.finish_edit();
+                }
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                // Only the direction matters, every wheel event changes the radius by one cell
+                let scroll = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => y as f64,
+                    MouseScrollDelta::PixelDelta(position) => position.y,
+                };
+                if scroll == 0.0 {
+                    return;
+                }
+
+                // With control held the wheel zooms around the cursor instead
+                if self.modifiers.control_key() {
+                    if let Some(cursor) = self.cursor_position {
+                        state.zoom_camera(scroll.signum() as i32, cursor);
+                    }
+                } else {
+                    state.change_brush_radius(scroll.signum() as i32);
+                }
             }
-            WindowEvent::KeyboardInput { event, .. } => {
-                if event.state == ElementState::Pressed {
-                    match event.logical_key {
-                        Key::Named(NamedKey::ArrowUp) | Key::Named(NamedKey::ArrowRight) => {
-                            state.cycle_material_up();
+            WindowEvent::ModifiersChanged(modifiers) => self.modifiers = modifiers.state(),
+            WindowEvent::KeyboardInput { event, .. } if event.state == ElementState::Pressed => {
+                let mut selected_tool = None;
+
+                match event.logical_key {
+                    Key::Character(ref c) if self.modifiers.control_key() => {
+                        match c.to_lowercase().as_str() {
+                            // Ctrl+Z undoes, Ctrl+Shift+Z redoes and Ctrl+Alt+Z undoes while also rewinding the simulation
+                            "z" if self.modifiers.shift_key() => state.redo(),
+                            "z" if self.modifiers.alt_key() => state.rewind(),
+                            "z" => state.undo(),
+                            "c" => {
+                                if let Some((from, to)) = self.selection {
+                                    state.copy(from, to);
+                                }
+                            }
+                            "x" => {
+                                if let Some((from, to)) = self.selection {
+                                    state.cut(from, to);
+                                }
+                            }
+                            "v" => selected_tool = Some(Tool::Paste),
+                            // Saves the selection as a new stamp file
+                            "s" => {
+                                if let Some((from, to)) = self.selection {
+                                    state.save_stamp(from, to);
+                                }
+                            }
+                            _ => {}
                         }
-                        Key::Named(NamedKey::ArrowDown) | Key::Named(NamedKey::ArrowLeft) => {
-                            state.cycle_material_down();
+                    }
+                    Key::Named(NamedKey::ArrowUp) | Key::Named(NamedKey::ArrowRight) => {
+                        state.cycle_material_up();
+                    }
+                    Key::Named(NamedKey::ArrowDown) | Key::Named(NamedKey::ArrowLeft) => {
+                        state.cycle_material_down();
+                    }
+                    Key::Named(NamedKey::Space) => state.toggle_pause(),
+                    Key::Named(NamedKey::Tab) => state.toggle_hud(),
+                    // F1 to F4 toggle the debug overlays, F1 to F3 slow the GPU backend down with readbacks
+                    Key::Named(NamedKey::F1) => {
+                        state.toggle_debug_overlay(DebugOverlay::MovedCells)
+                    }
+                    Key::Named(NamedKey::F2) => {
+                        state.toggle_debug_overlay(DebugOverlay::UnchangedChunks)
+                    }
+                    Key::Named(NamedKey::F3) => {
+                        state.toggle_debug_overlay(DebugOverlay::MoveDirection)
+                    }
+                    Key::Named(NamedKey::F4) => state.toggle_debug_overlay(DebugOverlay::Metadata),
+                    // Finish the polygon, without the point following the cursor
+                    Key::Named(NamedKey::Enter) => {
+                        if let Some(mut shape) = self.shape.take() {
+                            shape.points.pop();
+                            if shape.points.len() >= 3 {
+                                state.draw_shape(shape);
+                            }
                         }
-                        _ => {}
+                        update_tool_preview(self.tool, None, self.selection, state);
                     }
+                    // Cancels the shape, the selection and pasting
+                    Key::Named(NamedKey::Escape) => {
+                        self.shape = None;
+                        self.selection = None;
+                        self.selecting = false;
+                        if self.tool == Tool::Paste {
+                            selected_tool = Some(Tool::Brush);
+                        }
+                        update_tool_preview(self.tool, None, None, state);
+                    }
+                    // Number keys select the material with that number, 0 is air
+                    Key::Character(ref c) if let Ok(material) = c.parse() => {
+                        state.select_material(material)
+                    }
+                    Key::Character(ref c) => match c.as_str() {
+                        "." => state.step_simulation(),
+                        "+" | "=" => state.speed_up_simulation(),
+                        "-" => state.slow_down_simulation(),
+                        "[" => state.change_brush_radius(-1),
+                        "]" => state.change_brush_radius(1),
+                        "{" => state.change_spray_density(-0.05),
+                        "}" => state.change_spray_density(0.05),
+                        // Selects the brush, pressing it again cycles through the brush shapes
+                        "b" if self.tool == Tool::Brush => state.cycle_brush_shape(),
+                        "b" => selected_tool = Some(Tool::Brush),
+                        "f" => selected_tool = Some(Tool::Fill),
+                        // Rotates and flips the clipboard while pasting
+                        "r" if self.tool == Tool::Paste => state.rotate_clipboard(),
+                        "h" if self.tool == Tool::Paste => state.flip_clipboard_horizontally(),
+                        "v" if self.tool == Tool::Paste => state.flip_clipboard_vertically(),
+                        // Selects the filled rectangle, pressing it again toggles the outline
+                        "r" if self.tool == Tool::Shape(ShapeKind::FilledRectangle) => {
+                            selected_tool = Some(Tool::Shape(ShapeKind::OutlinedRectangle))
+                        }
+                        "r" => selected_tool = Some(Tool::Shape(ShapeKind::FilledRectangle)),
+                        "l" => selected_tool = Some(Tool::Shape(ShapeKind::Line)),
+                        "p" => selected_tool = Some(Tool::Shape(ShapeKind::Polygon)),
+                        "m" => state.cycle_placement_mode(),
+                        "c" => state.cycle_theme(),
+                        // Selects the select tool, S pans the camera down with the other WASD keys
+                        "e" => selected_tool = Some(Tool::Select),
+                        // Resizes the grid to fill the window
+                        "g" => state.fit_grid_to_window(),
+                        // Pan the camera by a tenth of the visible part
+                        "w" => state.pan_camera_by_view(0.0, -0.1),
+                        "a" => state.pan_camera_by_view(-0.1, 0.0),
+                        "s" => state.pan_camera_by_view(0.0, 0.1),
+                        "d" => state.pan_camera_by_view(0.1, 0.0),
+                        // Loads the next stamp file into the clipboard to paste it
+                        "t" if state.load_next_stamp() => selected_tool = Some(Tool::Paste),
+                        _ => {}
+                    },
+                    _ => {}
+                }
+
+                // Switching tools drops the unfinished shape
+                if let Some(tool) = selected_tool {
+                    self.tool = tool;
+                    self.shape = None;
+                    self.left_mouse_button_pressed = false;
+                    self.selecting = false;
+                    update_tool_preview(tool, None, self.selection, state);
                 }
             }
             WindowEvent::RedrawRequested => {
-                // Add sand if mouse is held down
-                if self.left_mouse_button_pressed {
-                    if let Some((x, y)) = self.cursor_position {
-                        state.add_material_at_cursor(x, y);
+                // Paint with the left and erase with the right mouse button while held down
+                if (self.left_mouse_button_pressed || self.right_mouse_button_pressed)
+                    && let Some((x, y)) = self.cursor_position
+                {
+                    let position = state.cursor_to_grid(x, y);
+                    let from = self.last_paint_position.unwrap_or(position);
+                    if self.left_mouse_button_pressed {
+                        state.add_material_line(from, position);
+                    } else {
+                        state.erase_line(from, position);
                     }
+                    self.last_paint_position = Some(position);
                 }
 
                 match state.render() {
@@ -124,5 +411,31 @@ impl ApplicationHandler for App {
             }
             _ => {}
         }
+
+        // Positions on the old grid mean nothing on the resized one
+        if state.grid_size() != grid_size {
+            self.shape = None;
+            self.selection = None;
+            self.selecting = false;
+            self.last_paint_position = None;
+            update_tool_preview(self.tool, None, None, state);
+        }
     }
 }
+
+/// Previews what the selected tool would do: the shape being drawn, the selection, the clipboard or the brush
+fn update_tool_preview(
+    tool: Tool,
+    shape: Option<&Shape>,
+    selection: Option<((i32, i32), (i32, i32))>,
+    state: &mut State,
+) {
+    let preview = match (tool, shape, selection) {
+        (_, Some(shape), _) => ToolPreview::Shape(shape),
+        (Tool::Select, _, Some((from, to))) => ToolPreview::Selection(from, to),
+        (Tool::Paste, _, _) => ToolPreview::Paste,
+        (Tool::Brush, _, _) => ToolPreview::Brush,
+        _ => ToolPreview::Nothing,
+    };
+    state.update_tool_preview(preview);
+}
@@ -0,0 +1,470 @@
+use crate::state::{BackendKind, Scene, Theme};
+use crate::{
+    HEIGHT, MAX_BRUSH_RADIUS, MAX_UPDATES_PER_SECOND, MIN_BRUSH_RADIUS, MIN_UPDATES_PER_SECOND,
+    RADIUS_ADD_PARTICLES, RESIZE_GRID_WITH_WINDOW, UNDO_MEMORY_BUDGET_MIB, UPDATES_PER_SECOND,
+    WIDTH,
+};
+use anyhow::{Context, bail};
+use std::path::{Path, PathBuf};
+
+const HEADLESS_TICKS: u32 = 1000; // Ticks simulated with --headless unless --ticks is given
+
+pub const USAGE: &str = "\
+Usage: sand [OPTIONS]
+
+Options:
+  --config <PATH>        Read options from a file, options given here override it
+  --width <CELLS>        Grid width
+  --height <CELLS>       Grid height
+  --tps <TICKS>          Simulation ticks per second
+  --brush-radius <CELLS> Initial brush radius
+  --scene <SCENE>        Initial grid: empty, sand-pile, noise, ledges or a stamp file
+  --seed <SEED>          Seed for generated scenes and spraying
+  --theme <THEME>        Colors: light, dark, high-contrast or colorblind
+  --backend <BACKEND>    Simulate on the cpu or on the gpu with a compute shader
+  --undo-budget <MIB>    Memory kept for undoing edits, 0 disables undo
+  --resize-grid-with-window
+                         Resize the grid to fill the window instead of scaling it
+  --headless             Simulate without a window and print the particle counts
+  --ticks <TICKS>        Ticks to simulate with --headless
+  --screenshot <PATH>    Save the grid as a PPM image after a --headless run
+  --help                 Print this help
+
+Config files contain one `option = value` per line without the leading dashes,
+lines starting with `#` are comments.";
+
+/// Settings the app starts with, from the command line and an optional config file
+pub struct Config {
+    pub width: u32,
+    pub height: u32,
+    pub updates_per_second: u32,
+    pub brush_radius: u32,
+    pub scene: Scene,
+    pub seed: u64,
+    pub theme: Theme,
+    pub backend: BackendKind,
+    /// Bytes kept for undoing edits
+    pub undo_memory_budget: usize,
+    pub resize_grid_with_window: bool,
+    /// Number of ticks to simulate without a window, None opens the window
+    pub headless: Option<u32>,
+    /// Where a headless run saves the grid as an image
+    pub screenshot: Option<PathBuf>,
+    pub help: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            width: WIDTH,
+            height: HEIGHT,
+            updates_per_second: UPDATES_PER_SECOND,
+            brush_radius: RADIUS_ADD_PARTICLES,
+            scene: Scene::Empty,
+            seed: 0,
+            theme: Theme::default(),
+            backend: BackendKind::default(),
+            undo_memory_budget: UNDO_MEMORY_BUDGET_MIB as usize * 1024 * 1024,
+            resize_grid_with_window: RESIZE_GRID_WITH_WINDOW,
+            headless: None,
+            screenshot: None,
+            help: false,
+        }
+    }
+}
+
+/// Options as given, unset ones fall back to the config file and then to the defaults
+#[derive(Default)]
+struct Options {
+    width: Option<u32>,
+    height: Option<u32>,
+    tps: Option<u32>,
+    brush_radius: Option<u32>,
+    scene: Option<String>,
+    seed: Option<u64>,
+    theme: Option<String>,
+    backend: Option<String>,
+    undo_budget: Option<u32>,
+    resize_grid_with_window: Option<bool>,
+    headless: Option<bool>,
+    ticks: Option<u32>,
+    screenshot: Option<String>,
+}
+
+impl Options {
+    /// Sets an option shared by the command line and config files, returns false for unknown names
+    fn set(&mut self, name: &str, value: &str) -> anyhow::Result<bool> {
+        match name {
+            "width" => self.width = Some(parse(name, value)?),
+            "height" => self.height = Some(parse(name, value)?),
+            "tps" => self.tps = Some(parse(name, value)?),
+            "brush-radius" => self.brush_radius = Some(parse(name, value)?),
+            "scene" => self.scene = Some(value.to_string()),
+            "seed" => self.seed = Some(parse(name, value)?),
+            "theme" => self.theme = Some(value.to_string()),
+            "backend" => self.backend = Some(value.to_string()),
+            "undo-budget" => self.undo_budget = Some(parse(name, value)?),
+            "resize-grid-with-window" => self.resize_grid_with_window = Some(parse(name, value)?),
+            "headless" => self.headless = Some(parse(name, value)?),
+            "ticks" => self.ticks = Some(parse(name, value)?),
+            "screenshot" => self.screenshot = Some(value.to_string()),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Fills the options not set here with the ones from `other`
+    fn or(self, other: Options) -> Options {
+        Options {
+            width: self.width.or(other.width),
+            height: self.height.or(other.height),
+            tps: self.tps.or(other.tps),
+            brush_radius: self.brush_radius.or(other.brush_radius),
+            scene: self.scene.or(other.scene),
+            seed: self.seed.or(other.seed),
+            theme: self.theme.or(other.theme),
+            backend: self.backend.or(other.backend),
+            undo_budget: self.undo_budget.or(other.undo_budget),
+            resize_grid_with_window: self
+                .resize_grid_with_window
+                .or(other.resize_grid_with_window),
+            headless: self.headless.or(other.headless),
+            ticks: self.ticks.or(other.ticks),
+            screenshot: self.screenshot.or(other.screenshot),
+        }
+    }
+
+    fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Reading config {} failed", path.display()))?;
+
+        let mut options = Options::default();
+        for (line_number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let Some((name, value)) = line.split_once('=') else {
+                bail!(
+                    "Expected `option = value` in line {} of config {}",
+                    line_number + 1,
+                    path.display()
+                );
+            };
+            let (name, value) = (name.trim(), value.trim().trim_matches('"'));
+            let known = options.set(name, value).with_context(|| {
+                format!("In line {} of config {}", line_number + 1, path.display())
+            })?;
+            if !known {
+                bail!(
+                    "Unknown option '{}' in line {} of config {}",
+                    name,
+                    line_number + 1,
+                    path.display()
+                );
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+impl Config {
+    /// Parses the command line arguments (without the program name)
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> anyhow::Result<Self> {
+        let mut options = Options::default();
+        let mut config_path = None;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let Some(flag) = arg.strip_prefix("--") else {
+                bail!("Unexpected argument '{}', see --help", arg);
+            };
+            // Values follow either after `=` or as the next argument
+            let (name, inline_value) = match flag.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (flag, None),
+            };
+
+            match name {
+                "help" => {
+                    return Ok(Self {
+                        help: true,
+                        ..Self::default()
+                    });
+                }
+                "headless" if inline_value.is_none() => options.headless = Some(true),
+                "resize-grid-with-window" if inline_value.is_none() => {
+                    options.resize_grid_with_window = Some(true)
+                }
+                _ => {
+                    let Some(value) = inline_value.or_else(|| args.next()) else {
+                        bail!("--{} expects a value", name);
+                    };
+                    if name == "config" {
+                        config_path = Some(value);
+                    } else if !options.set(name, &value)? {
+                        bail!("Unknown option --{}, see --help", name);
+                    }
+                }
+            }
+        }
+
+        if let Some(path) = config_path {
+            options = options.or(Options::load(Path::new(&path))?);
+        }
+        Self::from_options(options)
+    }
+
+    fn from_options(options: Options) -> anyhow::Result<Self> {
+        let defaults = Self::default();
+        let width = options.width.unwrap_or(defaults.width);
+        let height = options.height.unwrap_or(defaults.height);
+        let updates_per_second = options.tps.unwrap_or(defaults.updates_per_second);
+        let brush_radius = options.brush_radius.unwrap_or(defaults.brush_radius);
+
+        // The largest grid depends on the device, it is checked once the device is created
+        if width == 0 {
+            bail!("Width has to be at least 1");
+        }
+        if height == 0 {
+            bail!("Height has to be at least 1");
+        }
+        if !(MIN_UPDATES_PER_SECOND..=MAX_UPDATES_PER_SECOND).contains(&updates_per_second) {
+            bail!(
+                "Ticks per second have to be between {} and {}, got {}",
+                MIN_UPDATES_PER_SECOND,
+                MAX_UPDATES_PER_SECOND,
+                updates_per_second
+            );
+        }
+        if !(MIN_BRUSH_RADIUS..=MAX_BRUSH_RADIUS).contains(&brush_radius) {
+            bail!(
+                "Brush radius has to be between {} and {}, got {}",
+                MIN_BRUSH_RADIUS,
+                MAX_BRUSH_RADIUS,
+                brush_radius
+            );
+        }
+
+        let headless = match (options.headless.unwrap_or(false), options.ticks) {
+            (true, ticks) => {
+                // Headless runs simulate as fast as possible
+                if options.tps.is_some() {
+                    bail!(
+                        "--tps has no effect with --headless, use --ticks to choose how long to simulate"
+                    );
+                }
+                Some(ticks.unwrap_or(HEADLESS_TICKS))
+            }
+            (false, Some(_)) => bail!("--ticks only applies to --headless runs"),
+            (false, None) => None,
+        };
+        if headless.is_none() && options.screenshot.is_some() {
+            bail!("--screenshot only applies to --headless runs");
+        }
+
+        let scene = match &options.scene {
+            Some(scene) => Scene::parse(scene)?,
+            None => defaults.scene,
+        };
+        scene.check_fits(width, height)?;
+
+        let theme = match &options.theme {
+            Some(name) => match Theme::parse(name) {
+                Some(theme) => theme,
+                None => bail!(
+                    "Unknown theme '{}', expected one of {}",
+                    name,
+                    Theme::ALL.map(Theme::name).join(", ")
+                ),
+            },
+            None => defaults.theme,
+        };
+
+        let backend = match &options.backend {
+            Some(name) => match BackendKind::parse(name) {
+                Some(backend) => backend,
+                None => bail!(
+                    "Unknown backend '{}', expected one of {}",
+                    name,
+                    BackendKind::ALL.map(BackendKind::name).join(", ")
+                ),
+            },
+            None => defaults.backend,
+        };
+
+        Ok(Self {
+            width,
+            height,
+            updates_per_second,
+            brush_radius,
+            scene,
+            seed: options.seed.unwrap_or(defaults.seed),
+            theme,
+            backend,
+            undo_memory_budget: options
+                .undo_budget
+                .map_or(defaults.undo_memory_budget, |mib| {
+                    mib as usize * 1024 * 1024
+                }),
+            resize_grid_with_window: options
+                .resize_grid_with_window
+                .unwrap_or(defaults.resize_grid_with_window),
+            headless,
+            screenshot: options.screenshot.map(PathBuf::from),
+            help: false,
+        })
+    }
+}
+
+fn parse<T: std::str::FromStr>(name: &str, value: &str) -> anyhow::Result<T>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    value
+        .parse()
+        .with_context(|| format!("Invalid value '{}' for {}", value, name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_args(args: &str) -> anyhow::Result<Config> {
+        Config::from_args(args.split_whitespace().map(String::from))
+    }
+
+    /// Writes a config file to the temp directory, named after the test so tests don't share files
+    fn write_config(name: &str, text: &str) -> String {
+        let path =
+            std::env::temp_dir().join(format!("sand-config-{}-{}.conf", name, std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn error(args: &str) -> String {
+        match from_args(args) {
+            Ok(_) => panic!("'{}' was accepted", args),
+            Err(e) => format!("{:#}", e),
+        }
+    }
+
+    #[test]
+    fn defaults_without_arguments() -> anyhow::Result<()> {
+        let config = from_args("")?;
+        assert_eq!((config.width, config.height), (WIDTH, HEIGHT));
+        assert_eq!(config.updates_per_second, UPDATES_PER_SECOND);
+        assert_eq!(config.backend, BackendKind::Cpu);
+        assert_eq!(config.headless, None);
+        assert!(!config.help);
+        Ok(())
+    }
+
+    #[test]
+    fn values_follow_as_next_argument_or_after_equals() -> anyhow::Result<()> {
+        let config = from_args("--width 120 --height=80 --theme dark --backend=gpu --seed 7")?;
+        assert_eq!((config.width, config.height), (120, 80));
+        assert_eq!(config.theme, Theme::Dark);
+        assert_eq!(config.backend, BackendKind::Gpu);
+        assert_eq!(config.seed, 7);
+        Ok(())
+    }
+
+    #[test]
+    fn flags_take_an_optional_value() -> anyhow::Result<()> {
+        assert_eq!(from_args("--headless")?.headless, Some(HEADLESS_TICKS));
+        assert_eq!(from_args("--headless=false")?.headless, None);
+        assert_eq!(from_args("--headless --ticks=5")?.headless, Some(5));
+        assert!(from_args("--resize-grid-with-window")?.resize_grid_with_window);
+        assert!(!from_args("--resize-grid-with-window=false")?.resize_grid_with_window);
+        Ok(())
+    }
+
+    #[test]
+    fn screenshots_are_saved_by_headless_runs() -> anyhow::Result<()> {
+        let config = from_args("--headless --screenshot grid.ppm")?;
+        assert_eq!(config.screenshot, Some(PathBuf::from("grid.ppm")));
+        assert_eq!(from_args("--headless")?.screenshot, None);
+        Ok(())
+    }
+
+    #[test]
+    fn undo_budget_is_given_in_mib() -> anyhow::Result<()> {
+        assert_eq!(
+            from_args("--undo-budget 3")?.undo_memory_budget,
+            3 * 1024 * 1024
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn command_line_overrides_config_file() -> anyhow::Result<()> {
+        let path = write_config(
+            "precedence",
+            "# Comment\nwidth = 90\nheight = 70\n\ntheme = \"light\"\n",
+        );
+        let config = from_args(&format!("--height 50 --config {}", path))?;
+        std::fs::remove_file(&path)?;
+
+        assert_eq!((config.width, config.height), (90, 50));
+        assert_eq!(config.theme, Theme::Light);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_unknown_options() {
+        assert!(error("--colour red").contains("Unknown option --colour"));
+        assert!(error("width").contains("Unexpected argument 'width'"));
+        assert!(error("--width").contains("--width expects a value"));
+        assert!(error("--theme sepia").contains("Unknown theme 'sepia'"));
+        assert!(error("--backend vulkan").contains("Unknown backend 'vulkan'"));
+        assert!(error("--scene volcano").contains("Unknown scene 'volcano'"));
+        assert!(error("--width many").contains("Invalid value 'many' for width"));
+    }
+
+    #[test]
+    fn rejects_unknown_options_in_config_files() {
+        let path = write_config("unknown", "width = 90\ncolour = red\n");
+        let message = error(&format!("--config {}", path));
+        std::fs::remove_file(&path).unwrap();
+        assert!(
+            message.contains("Unknown option 'colour' in line 2"),
+            "{}",
+            message
+        );
+
+        let path = write_config("malformed", "width 90\n");
+        let message = error(&format!("--config {}", path));
+        std::fs::remove_file(&path).unwrap();
+        assert!(
+            message.contains("Expected `option = value` in line 1"),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn rejects_values_out_of_range() {
+        assert!(error("--width 0").contains("Width has to be at least 1"));
+        assert!(error("--height=0").contains("Height has to be at least 1"));
+        assert!(error("--tps 0").contains("Ticks per second have to be between"));
+        assert!(
+            error(&format!("--brush-radius {}", MAX_BRUSH_RADIUS + 1))
+                .contains("Brush radius has to be between")
+        );
+    }
+
+    #[test]
+    fn rejects_combinations_without_effect() {
+        assert!(error("--headless --tps 30").contains("--tps has no effect with --headless"));
+        assert!(error("--ticks 10").contains("--ticks only applies to --headless runs"));
+        assert!(
+            error("--screenshot grid.ppm").contains("--screenshot only applies to --headless runs")
+        );
+        assert!(
+            error("--scene stamps/basin.stamp --width 10").contains("doesn't fit into the 10x")
+        );
+    }
+}
@@ -1,12 +1,30 @@
 use crate::app::App;
+use crate::config::Config;
 use winit::event_loop::EventLoop;
 
 mod app;
+mod config;
 mod state;
 
-const MS_PER_SIMULATION: u64 = 16; // Update simulation every 16ms (~60 updates/sec)
-const RADIUS_ADD_PARTICLES: u32 = 15;
-const WIDTH: u32 = 600;
+const UPDATES_PER_SECOND: u32 = 60; // Initial simulation speed unless given with --tps, can be changed at runtime
+const MIN_UPDATES_PER_SECOND: u32 = 1;
+const MAX_UPDATES_PER_SECOND: u32 = 240;
+const MAX_SIMULATION_STEPS_PER_FRAME: u32 = 4; // Catch up on slow frames with at most 4 updates per frame
+const RADIUS_ADD_PARTICLES: u32 = 15; // Initial brush radius unless given with --brush-radius, can be changed at runtime
+const MIN_BRUSH_RADIUS: u32 = 0;
+const MAX_BRUSH_RADIUS: u32 = 100;
+const UNDO_MEMORY_BUDGET_MIB: u32 = 64; // Memory kept for undoing edits unless given with --undo-budget, the oldest edits are forgotten first
+const STAMP_DIRECTORY: &str = "stamps"; // Saved selections and pre-built stamps, relative to the working directory
+const MAX_SAVED_STAMPS: u32 = 9999; // Saved stamps are numbered up to this, saving fails once all numbers are taken
+const PALETTE_SWATCH_SIZE: u32 = 24; // Size of the material swatches in the palette bar in pixels
+const STATS_INTERVAL_MS: u64 = 500; // How often the HUD measures frame and tick rates and counts particles
+const HUD_TEXT_SCALE: u32 = 2; // Every pixel of the HUD font is drawn this many pixels wide and high
+const MAX_ZOOM: u32 = 16; // Most pixels per cell the camera zooms in to, relative to the unzoomed grid
+const PAN_THRESHOLD: f64 = 3.0; // Pixels the cursor moves with the middle mouse button held before it pans instead of picking a material
+const RESIZE_GRID_WITH_WINDOW: bool = false; // Resize the grid to fill the window instead of scaling it, unless given with --resize-grid-with-window
+const PIXELS_PER_CELL: u32 = 2; // Size of a cell when the grid is fitted to the window
+const DEBUG_CHUNK_SIZE: u32 = 16; // Cells per side of the chunks the unchanged chunk overlay dims
+const WIDTH: u32 = 600; // Grid size unless given with --width and --height
 const HEIGHT: u32 = 400;
 
 fn main() {
@@ -15,6 +33,28 @@ fn main() {
     // This means if you don't include env_logger::init(), wgpu will fail silently.
     env_logger::init();
 
+    // Invalid options are reported before anything is started
+    let config = match Config::from_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(e) => {
+            log::error!("Invalid options: {:#}", e);
+            std::process::exit(2);
+        }
+    };
+
+    if config.help {
+        println!("{}", config::USAGE);
+        return;
+    }
+
+    if let Some(ticks) = config.headless {
+        if let Err(e) = pollster::block_on(state::run_headless(&config, ticks)) {
+            log::error!("Headless run failed: {:#}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let event_loop = match EventLoop::new() {
         Ok(event_loop) => event_loop,
         Err(e) => {
@@ -23,11 +63,11 @@ fn main() {
         }
     };
 
-    let mut app = App::default();
+    let mut app = App::new(config);
     match event_loop.run_app(&mut app) {
         Ok(_) => (),
         Err(e) => {
-            log::error!("Running app failed {}", e.to_string());
+            log::error!("Running app failed {}", e);
         }
     }
 }
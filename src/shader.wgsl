@@ -4,9 +4,9 @@ struct VertexOutput {
     @location(0) tex_coords: vec2<f32>,
 }
 
-// Particle buffer (each particle is 1 byte - u8)
+// Particle grid, one texel per cell holding its material
 @group(0) @binding(0)
-var<storage, read> particles: array<u32>;
+var particles: texture_2d<u32>;
 
 // Uniforms for grid dimensions
 @group(0) @binding(1)
@@ -20,6 +20,125 @@ var<uniform> mouse_pos: vec2<f32>;
 @group(0) @binding(3)
 var<uniform> selected_material: u32;
 
+// Brush used for painting, see Brush::to_uniform
+struct Brush {
+    // Radius in grid cells
+    radius: f32,
+    // 0: circle, 1: square, 2: horizontal line, 3: spray
+    shape: u32,
+    // 0: overwrite, 1: only into air, 2: replace material
+    mode: u32,
+    replaced_material: u32,
+}
+
+@group(0) @binding(4)
+var<uniform> brush: Brush;
+
+// What the overlay previews, see Shape::contains
+struct ToolPreview {
+    // 0: brush, 1: nothing, 2: filled rectangle, 3: outlined rectangle, 4: line, 5: polygon,
+    // 6: selection from the first to the second point, 7: paste centered at the mouse
+    kind: u32,
+    point_count: u32,
+    // Points of the shape in grid cells, only xy is used. When pasting the first point is the size of the clipboard.
+    points: array<vec4<f32>, 32>,
+}
+
+@group(0) @binding(5)
+var<uniform> tool_preview: ToolPreview;
+
+// Palette bar showing the registered materials, see MATERIALS
+struct Palette {
+    // Width and height of a swatch in pixels
+    swatch_size: f32,
+    material_count: u32,
+}
+
+@group(0) @binding(6)
+var<uniform> palette: Palette;
+
+// Glyphs of the HUD font, 16 glyphs per row in cells of 6x8 pixels, see font.rs
+@group(0) @binding(7)
+var font_atlas: texture_2d<f32>;
+
+// Text of the HUD below the palette bar, see Buffers::update_hud_buffer
+struct Hud {
+    // Characters per line and lines, 0 lines hide the HUD
+    columns: u32,
+    rows: u32,
+    // Pixels per font pixel
+    scale: f32,
+    // Glyph indices, one byte each, packed line by line
+    glyphs: array<vec4<u32>, 32>,
+}
+
+@group(0) @binding(8)
+var<uniform> hud: Hud;
+
+// Where the grid is drawn in the window, see Viewport
+struct Viewport {
+    // Top left corner of the grid in pixels
+    offset: vec2<f32>,
+    // Pixels per cell
+    scale: f32,
+}
+
+@group(0) @binding(9)
+var<uniform> viewport: Viewport;
+
+// Which part of the grid is visible inside the viewport, see Camera
+struct Camera {
+    // Top left corner of the visible part in grid cells
+    origin: vec2<f32>,
+    // Whole number, 1 shows the whole grid
+    zoom: f32,
+}
+
+@group(0) @binding(10)
+var<uniform> camera: Camera;
+
+// Color of every possible cell value in a single row, see Buffers::update_palette_texture.
+// Alpha is how much the color varies between particles.
+@group(0) @binding(11)
+var palette_colors: texture_2d<f32>;
+
+// Metadata of the particle in every cell, see SimulationBackend::read_metadata
+@group(0) @binding(12)
+var particle_metadata: texture_2d<u32>;
+
+// Colors besides the materials, see Theme
+struct Theme {
+    // Around the grid
+    background: vec4<f32>,
+    // HUD text and the border of the selected swatch
+    text: vec4<f32>,
+    // Translucent box behind the HUD text
+    panel: vec4<f32>,
+}
+
+@group(0) @binding(13)
+var<uniform> theme: Theme;
+
+// Direction every cell's particle moved in during the last tick, see MotionTracker::motion
+// 0: didn't move, 1: down, 2: down left, 3: down right
+@group(0) @binding(14)
+var cell_motion: texture_2d<u32>;
+
+// 1 for every chunk that changed during the last tick, 0 for unchanged ones
+@group(0) @binding(15)
+var chunk_activity: texture_2d<u32>;
+
+// Debug overlays drawn on top of the grid
+struct DebugOverlay {
+    // 1: moved cells, 2: unchanged chunks, 4: move direction, 8: metadata
+    enabled: u32,
+    // Cells per side of a chunk
+    chunk_size: u32,
+}
+
+@group(0) @binding(16)
+var<uniform> debug_overlay: DebugOverlay;
+
 @vertex
 fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
     var out: VertexOutput;
@@ -34,53 +153,279 @@ fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
     return out;
 }
 
-@fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    // Calculate pixel coordinates
-    let pixel_x = u32(in.tex_coords.x * f32(grid_dims.x));
-    let pixel_y = u32(in.tex_coords.y * f32(grid_dims.y));
-    
-    // Get particle index
-    let index = pixel_y * grid_dims.x + pixel_x;
-    
-    // Read particle type from buffer (4 bytes at a time, extract the one we need)
-    let word_index = index / 4u;
-    let byte_offset = index % 4u;
-    let word = particles[word_index];
-    
-    // Extract the byte we need
-    let particle_type = (word >> (byte_offset * 8u)) & 0xFFu;
+// Whether the cell at the offset from the brush center is part of the brush
+fn brush_covers(dx: f32, dy: f32) -> bool {
+    switch (brush.shape) {
+        case 1u: {
+            return abs(dx) <= brush.radius && abs(dy) <= brush.radius;
+        }
+        case 2u: {
+            return abs(dx) <= brush.radius && dy == 0.0;
+        }
+        default: {
+            return dx * dx + dy * dy <= brush.radius * brush.radius;
+        }
+    }
+}
+
+// Whether the brush paints into a cell containing the particle type
+fn brush_places_into(particle_type: u32) -> bool {
+    switch (brush.mode) {
+        case 1u: {
+            return particle_type == 0u;
+        }
+        case 2u: {
+            return particle_type == brush.replaced_material;
+        }
+        default: {
+            return true;
+        }
+    }
+}
+
+fn distance_to_segment(p: vec2<f32>, a: vec2<f32>, b: vec2<f32>) -> f32 {
+    let line = b - a;
+    let length_squared = dot(line, line);
+
+    // Project the point onto the line to find the closest point on the segment
+    var t = 0.0;
+    if (length_squared > 0.0) {
+        t = clamp(dot(p - a, line) / length_squared, 0.0, 1.0);
+    }
+
+    return length(p - (a + t * line));
+}
+
+// Whether the cell is part of the previewed shape, lines and outlines are as thick as the brush radius
+fn shape_contains(cell: vec2<f32>) -> bool {
+    if (tool_preview.point_count == 0u) {
+        return false;
+    }
+
+    let thickness = max(brush.radius, 1.0);
+    let first = tool_preview.points[0].xy;
+    let last = tool_preview.points[tool_preview.point_count - 1u].xy;
+
+    switch (tool_preview.kind) {
+        case 2u, 3u: {
+            let low = min(first, last);
+            let high = max(first, last);
+            if (any(cell < low) || any(cell > high)) {
+                return false;
+            }
+
+            // The outline grows inwards
+            return tool_preview.kind == 2u
+                || any(cell - low < vec2<f32>(thickness))
+                || any(high - cell < vec2<f32>(thickness));
+        }
+        case 4u: {
+            return distance_to_segment(cell, first, last) <= thickness / 2.0;
+        }
+        case 5u: {
+            // Even-odd rule, the edges are included so that unfinished polygons stay visible
+            var inside = false;
+            var previous = last;
+            for (var i = 0u; i < tool_preview.point_count; i++) {
+                let current = tool_preview.points[i].xy;
+                if (distance_to_segment(cell, previous, current) <= 0.5) {
+                    return true;
+                }
+
+                if ((current.y > cell.y) != (previous.y > cell.y)
+                    && cell.x < (previous.x - current.x) * (cell.y - current.y) / (previous.y - current.y) + current.x) {
+                    inside = !inside;
+                }
+                previous = current;
+            }
+            return inside;
+        }
+        default: {
+            return false;
+        }
+    }
+}
+
+// Swatch of the palette bar in the top left corner at the pixel, or -1. Matches State::palette_swatch_at.
+fn palette_swatch_at(pixel: vec2<f32>) -> i32 {
+    let size = palette.swatch_size;
+    let gap = floor(size / 4.0);
+    let position = pixel - vec2<f32>(gap);
+    let swatch = floor(position.x / (size + gap));
+    if (position.x < 0.0 || position.y < 0.0 || position.y >= size
+        || swatch >= f32(palette.material_count) || position.x - swatch * (size + gap) >= size) {
+        return -1;
+    }
+    return i32(swatch);
+}
+
+// Coverage of the HUD text at the pixel (0.0 or 1.0), or -1.0 outside of the HUD
+fn hud_text_at(pixel: vec2<f32>) -> f32 {
+    let cell_size = vec2<f32>(6.0, 8.0);
+    let gap = floor(palette.swatch_size / 4.0);
+    // One cell of padding around the text, which starts below the palette bar
+    let origin = vec2<f32>(gap, palette.swatch_size + gap * 2.0);
+    let position = floor((pixel - origin) / hud.scale) - cell_size;
+    let cell = floor(position / cell_size);
+    if (any(cell < vec2<f32>(-1.0)) || cell.x > f32(hud.columns) || cell.y > f32(hud.rows)) {
+        return -1.0;
+    }
+    if (any(cell < vec2<f32>(0.0)) || cell.x >= f32(hud.columns) || cell.y >= f32(hud.rows)) {
+        return 0.0;
+    }
+
+    let index = u32(cell.y) * hud.columns + u32(cell.x);
+    let glyph = (hud.glyphs[index / 16u][(index / 4u) % 4u] >> ((index % 4u) * 8u)) & 0xFFu;
+    let in_cell = vec2<u32>(position - cell * cell_size);
+    let atlas_position = vec2<u32>(glyph % 16u, glyph / 16u) * vec2<u32>(6u, 8u) + in_cell;
+    return textureLoad(font_atlas, atlas_position, 0).r;
+}
+
+// Whether the cell is on the outline of the selection
+fn selection_outline_contains(cell: vec2<f32>) -> bool {
+    let low = min(tool_preview.points[0].xy, tool_preview.points[1].xy);
+    let high = max(tool_preview.points[0].xy, tool_preview.points[1].xy);
+    return all(cell >= low) && all(cell <= high)
+        && (any(cell == low) || any(cell == high));
+}
+
+// Color of a cell value in the current palette
+fn material_color(material: u32) -> vec4<f32> {
+    return vec4<f32>(textureLoad(palette_colors, vec2<u32>(material, 0u), 0).rgb, 1.0);
+}
+
+// Color of a single particle, brightened or darkened by the variation picked when it was placed
+fn particle_color(material: u32, metadata: u32) -> vec4<f32> {
+    let entry = textureLoad(palette_colors, vec2<u32>(material, 0u), 0);
+    let variation = (f32(metadata) / 127.5 - 1.0) * entry.a;
+    return vec4<f32>(entry.rgb * (1.0 + variation), 1.0);
+}
+
+// Draws the enabled debug overlays over the color of the cell
+fn debug_overlay_color(color: vec4<f32>, cell: vec2<u32>, metadata: u32) -> vec4<f32> {
+    var result = color;
+
+    // Metadata from dark blue for 0 to bright red for 255
+    if ((debug_overlay.enabled & 8u) != 0u) {
+        let value = f32(metadata) / 255.0;
+        result = vec4<f32>(value, 0.2, 1.0 - value, 1.0);
+    }
+
+    let motion = textureLoad(cell_motion, cell, 0).r;
+    if ((debug_overlay.enabled & 1u) != 0u && motion != 0u) {
+        result = mix(result, vec4<f32>(1.0, 0.0, 1.0, 1.0), 0.6);
+    }
+
+    // Red falls straight down, green moves down left and blue down right, yellow moves left and
+    // cyan right, white rises straight up, orange moves up left and purple up right
+    if ((debug_overlay.enabled & 4u) != 0u && motion != 0u) {
+        let direction_colors = array<vec4<f32>, 8>(
+            vec4<f32>(1.0, 0.0, 0.0, 1.0),
+            vec4<f32>(0.0, 0.8, 0.0, 1.0),
+            vec4<f32>(0.0, 0.3, 1.0, 1.0),
+            vec4<f32>(1.0, 0.9, 0.0, 1.0),
+            vec4<f32>(0.0, 0.9, 0.9, 1.0),
+            vec4<f32>(1.0, 1.0, 1.0, 1.0),
+            vec4<f32>(1.0, 0.5, 0.0, 1.0),
+            vec4<f32>(0.6, 0.2, 1.0, 1.0),
+        );
+        result = direction_colors[motion - 1u];
+    }
+
+    if ((debug_overlay.enabled & 2u) != 0u
+        && textureLoad(chunk_activity, cell / debug_overlay.chunk_size, 0).r == 0u) {
+        result = vec4<f32>(result.rgb * 0.4, 1.0);
+    }
+
+    return result;
+}
+
+// Color of the grid cell including the previews of the selected tool
+fn cell_color(pixel_x: u32, pixel_y: u32) -> vec4<f32> {
+    let particle_type = textureLoad(particles, vec2<u32>(pixel_x, pixel_y), 0).r;
+    let metadata = textureLoad(particle_metadata, vec2<u32>(pixel_x, pixel_y), 0).r;
 
     // Base color based on particle type
-    var color = vec4<f32>(1.0, 1.0, 1.0, 1.0);  // Air - white
-    
-    if (particle_type == 1u) {
-        // Sand - sandy color
-        color = vec4<f32>(0.76, 0.70, 0.50, 1.0);
-    } else if (particle_type == 2u) {
-        // Stone - gray color
-        color = vec4<f32>(0.57, 0.56, 0.52, 1.0);
+    var color = particle_color(particle_type, metadata);
+    if (debug_overlay.enabled != 0u) {
+        color = debug_overlay_color(color, vec2<u32>(pixel_x, pixel_y), metadata);
+    }
+
+    // Darken the cells of the shape being drawn
+    if (shape_contains(vec2<f32>(f32(pixel_x), f32(pixel_y))) && brush_places_into(particle_type)) {
+        color = color * 0.7;
+    }
+
+    if (tool_preview.kind == 6u && selection_outline_contains(vec2<f32>(f32(pixel_x), f32(pixel_y)))) {
+        color = color * 0.5;
     }
 
-    // Make circle around mouse darker (only if mouse is in window)
+    // Darken the cells the clipboard would be pasted into, matching ParticleManager::paste
+    if (tool_preview.kind == 7u && mouse_pos.x >= 0.0 && mouse_pos.y >= 0.0) {
+        let size = tool_preview.points[0].xy;
+        let mouse_grid = floor(mouse_pos * vec2<f32>(grid_dims));
+        let low = mouse_grid - floor(size / 2.0);
+        let cell = vec2<f32>(f32(pixel_x), f32(pixel_y));
+        if (all(cell >= low) && all(cell < low + size) && brush_places_into(particle_type)) {
+            color = color * 0.8;
+        }
+    }
+
+    // Darken the cells the brush would paint (only if mouse is in window)
     // Mouse position is set to negative values when outside window
-    if (mouse_pos.x >= 0.0 && mouse_pos.y >= 0.0) {
-        let radius = 15.0;
-        
-        // Convert mouse position from normalized coords to grid coords
-        let mouse_grid_x = mouse_pos.x * f32(grid_dims.x);
-        let mouse_grid_y = mouse_pos.y * f32(grid_dims.y);
+    if (tool_preview.kind == 0u && mouse_pos.x >= 0.0 && mouse_pos.y >= 0.0) {
+        // Convert mouse position from normalized coords to the grid cell under the cursor
+        let mouse_grid_x = floor(mouse_pos.x * f32(grid_dims.x));
+        let mouse_grid_y = floor(mouse_pos.y * f32(grid_dims.y));
         
-        // Calculate distance from current pixel to mouse position
+        // Calculate offset from current pixel to mouse position
         let dx = f32(pixel_x) - mouse_grid_x;
         let dy = f32(pixel_y) - mouse_grid_y;
-        let distance = sqrt(dx * dx + dy * dy);
         
-        // If within radius, darken the color
-        if (distance <= radius) {
-            color = color * 0.7;  // Darken by multiplying by 0.7
+        if (brush_covers(dx, dy) && brush_places_into(particle_type)) {
+            if (brush.shape == 3u) {
+                color = color * 0.85;  // The spray only paints some of the cells, so darken less
+            } else {
+                color = color * 0.7;  // Darken by multiplying by 0.7
+            }
         }
     }
 
     return color;
 }
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // The palette bar is drawn on top of the grid, the selected material gets a thick border in the text color
+    let swatch = palette_swatch_at(in.position.xy);
+    if (swatch >= 0) {
+        let size = palette.swatch_size;
+        let gap = floor(size / 4.0);
+        let inside = in.position.xy - vec2<f32>(gap + f32(swatch) * (size + gap), gap);
+        let distance_to_edge = min(min(inside.x, inside.y), min(size - inside.x, size - inside.y));
+        if (u32(swatch) == selected_material && distance_to_edge < 3.0) {
+            return theme.text;
+        }
+        if (distance_to_edge < 1.0) {
+            return vec4<f32>(0.3, 0.3, 0.3, 1.0);
+        }
+        return material_color(u32(swatch));
+    }
+
+    // The grid is scaled and centered by the viewport, the bars around it have the background color.
+    // Inside the viewport the camera shows the visible part of the grid.
+    var color = theme.background;
+    let view_position = (in.position.xy - viewport.offset) / viewport.scale;
+    if (all(view_position >= vec2<f32>(0.0)) && all(view_position < vec2<f32>(grid_dims))) {
+        let grid_position = min(floor(camera.origin + view_position / camera.zoom), vec2<f32>(grid_dims - 1u));
+        color = cell_color(u32(grid_position.x), u32(grid_position.y));
+    }
+
+    // HUD text on a translucent box
+    let text = hud_text_at(in.position.xy);
+    if (hud.rows > 0u && text >= 0.0) {
+        color = mix(mix(color, theme.panel, 0.75), theme.text, text);
+    }
+
+    return color;
+}
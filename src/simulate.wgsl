@@ -0,0 +1,183 @@
+// Simulation parameters
+struct Params {
+    width: u32,
+    height: u32,
+    // Offset of the 2x2 Margolus blocks (0 or 1 on each axis)
+    offset_x: u32,
+    offset_y: u32,
+    // Number of the tick, seeds the direction water flows in
+    tick: u32,
+    _padding0: u32,
+    _padding1: u32,
+    _padding2: u32,
+}
+
+// Particle grid (one u32 per cell), the material in the lowest byte and the particle's metadata in the next one
+@group(0) @binding(0)
+var<storage, read_write> cells: array<u32>;
+
+@group(0) @binding(1)
+var<uniform> params: Params;
+
+// Cells packed into bytes (4 cells per u32), copied into the particle grid and metadata textures of the render pass.
+// The rows of all materials are followed by the rows of all metadata, each row starts at a multiple of 256 bytes
+// as texture copies require.
+@group(0) @binding(2)
+var<storage, read_write> packed: array<u32>;
+
+const AIR: u32 = 0u;
+const SAND: u32 = 1u;
+const STONE: u32 = 2u;
+const WATER: u32 = 3u;
+// Cells outside the grid behave like an immovable wall
+const WALL: u32 = 0xFFFFFFFFu;
+
+fn in_grid(x: i32, y: i32) -> bool {
+    return x >= 0 && y >= 0 && x < i32(params.width) && y < i32(params.height);
+}
+
+fn get_cell(x: i32, y: i32) -> u32 {
+    if (!in_grid(x, y)) {
+        return WALL;
+    }
+    return cells[u32(y) * params.width + u32(x)];
+}
+
+fn set_cell(x: i32, y: i32, value: u32) {
+    if (in_grid(x, y)) {
+        cells[u32(y) * params.width + u32(x)] = value;
+    }
+}
+
+fn material(cell: u32) -> u32 {
+    return cell & 0xFFu;
+}
+
+// Heavier particles sink into lighter ones, walls and materials without rules never move.
+// Matches density in simulate.rs.
+fn density(cell: u32) -> u32 {
+    if (cell == WALL) {
+        return 255u;
+    }
+    switch (material(cell)) {
+        case AIR: {
+            return 0u;
+        }
+        case WATER: {
+            return 1u;
+        }
+        case SAND, STONE: {
+            return 2u;
+        }
+        default: {
+            return 255u;
+        }
+    }
+}
+
+// Integer hash (PCG) for random numbers that differ between blocks and ticks
+fn hash(value: u32) -> u32 {
+    let state = value * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn falls(cell: u32) -> bool {
+    return material(cell) == SAND || material(cell) == STONE || material(cell) == WATER;
+}
+
+fn sinks_into(cell: u32, other: u32) -> bool {
+    return density(cell) > density(other);
+}
+
+fn slides(cell: u32) -> bool {
+    return material(cell) == SAND || material(cell) == WATER;
+}
+
+// Every invocation owns one 2x2 block, so no two invocations touch the same cell
+@compute @workgroup_size(8, 8)
+fn step_blocks(@builtin(global_invocation_id) id: vec3<u32>) {
+    let x = i32(id.x * 2u) - i32(params.offset_x);
+    let y = i32(id.y * 2u) - i32(params.offset_y);
+
+    if (x >= i32(params.width) || y >= i32(params.height)) {
+        return;
+    }
+
+    var top_left = get_cell(x, y);
+    var top_right = get_cell(x + 1, y);
+    var bottom_left = get_cell(x, y + 1);
+    var bottom_right = get_cell(x + 1, y + 1);
+
+    // Swapping the cells moves the particle along with its metadata, into air or sinking into water
+
+    // Sand, stone and water fall straight down
+    if (falls(top_left) && sinks_into(top_left, bottom_left)) {
+        let moved = top_left;
+        top_left = bottom_left;
+        bottom_left = moved;
+    }
+    if (falls(top_right) && sinks_into(top_right, bottom_right)) {
+        let moved = top_right;
+        top_right = bottom_right;
+        bottom_right = moved;
+    }
+
+    // Sand and water that are blocked slide down diagonally
+    if (slides(top_left) && sinks_into(top_left, bottom_right)) {
+        let moved = top_left;
+        top_left = bottom_right;
+        bottom_right = moved;
+    }
+    if (slides(top_right) && sinks_into(top_right, bottom_left)) {
+        let moved = top_right;
+        top_right = bottom_left;
+        bottom_left = moved;
+    }
+
+    // Water that can't fall flows sideways, in the top row only where the cell below it is blocked.
+    // Every block picks a random direction, always flowing both ways would just swap the cells back
+    // when the same blocks come around again.
+    let flow_right = (hash(hash(params.tick) ^ (id.y * 65536u + id.x)) & 1u) == 0u;
+    if ((flow_right && material(top_left) == WATER && material(top_right) == AIR && material(bottom_left) != AIR)
+        || (!flow_right && material(top_right) == WATER && material(top_left) == AIR && material(bottom_right) != AIR)) {
+        let moved = top_left;
+        top_left = top_right;
+        top_right = moved;
+    }
+    if ((flow_right && material(bottom_left) == WATER && material(bottom_right) == AIR)
+        || (!flow_right && material(bottom_right) == WATER && material(bottom_left) == AIR)) {
+        let moved = bottom_left;
+        bottom_left = bottom_right;
+        bottom_right = moved;
+    }
+
+    set_cell(x, y, top_left);
+    set_cell(x + 1, y, top_right);
+    set_cell(x, y + 1, bottom_left);
+    set_cell(x + 1, y + 1, bottom_right);
+}
+
+// Packs the cells into bytes for the particle grid texture read by the render pass.
+// Every invocation packs one word, x is the word in the row and y the row.
+@compute @workgroup_size(64)
+fn pack(@builtin(global_invocation_id) id: vec3<u32>) {
+    let words_per_row = (params.width + 255u) / 256u * 64u;
+    let row = id.y;
+    if (id.x >= words_per_row || row >= params.height * 2u) {
+        return;
+    }
+
+    // Rows past the grid hold the metadata, which is the second byte of the cells
+    let y = row % params.height;
+    let shift = row / params.height * 8u;
+    var word = 0u;
+    for (var byte_offset = 0u; byte_offset < 4u; byte_offset++) {
+        let x = id.x * 4u + byte_offset;
+        if (x < params.width) {
+            word = word | (((cells[y * params.width + x] >> shift) & 0xFFu) << (byte_offset * 8u));
+        }
+    }
+
+    packed[row * words_per_row + id.x] = word;
+}
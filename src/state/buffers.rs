@@ -1,12 +1,47 @@
-use wgpu;
+use super::camera::Camera;
+use super::font;
+use super::particle_manager::{
+    Brush, MATERIALS, MAX_MATERIALS, MAX_SHAPE_POINTS, Theme, chunk_count,
+};
+use super::viewport::Viewport;
+use crate::{DEBUG_CHUNK_SIZE, HUD_TEXT_SCALE, PALETTE_SWATCH_SIZE};
+
+// Kinds of the tool preview besides the shapes, see shader.wgsl
+pub const PREVIEW_BRUSH: u32 = 0;
+pub const PREVIEW_NOTHING: u32 = 1;
+pub const PREVIEW_SELECTION: u32 = 6;
+pub const PREVIEW_PASTE: u32 = 7;
+
+// Debug overlays drawn on top of the grid, bits of the debug overlay uniform in shader.wgsl
+pub const OVERLAY_MOVED_CELLS: u32 = 1;
+pub const OVERLAY_UNCHANGED_CHUNKS: u32 = 2;
+pub const OVERLAY_MOVE_DIRECTION: u32 = 4;
+pub const OVERLAY_METADATA: u32 = 8;
+
+/// Most characters per line and lines the HUD uniform in shader.wgsl has room for
+pub const HUD_MAX_COLUMNS: usize = 32;
+pub const HUD_MAX_ROWS: usize = 16;
 
 pub struct Buffers {
-    pub particle_grid_buffer: wgpu::Buffer,
-    pub grid_dims_buffer: wgpu::Buffer,
+    pub particle_grid_texture: wgpu::Texture,
+    pub particle_metadata_texture: wgpu::Texture,
     pub mouse_position_buffer: wgpu::Buffer,
     pub selected_material_buffer: wgpu::Buffer,
-    pub bind_group: wgpu::BindGroup,
+    pub brush_buffer: wgpu::Buffer,
+    pub tool_preview_buffer: wgpu::Buffer,
+    pub hud_buffer: wgpu::Buffer,
+    pub viewport_buffer: wgpu::Buffer,
+    pub camera_buffer: wgpu::Buffer,
+    pub palette_texture: wgpu::Texture,
+    pub theme_buffer: wgpu::Buffer,
+    pub cell_motion_texture: wgpu::Texture,
+    pub chunk_activity_texture: wgpu::Texture,
+    pub debug_overlay_buffer: wgpu::Buffer,
     pub bind_group_layout: wgpu::BindGroupLayout,
+    // Only bound to the shader, of these only the grid dimensions change when the grid is resized
+    grid_dims_buffer: wgpu::Buffer,
+    palette_buffer: wgpu::Buffer,
+    font_atlas_texture: wgpu::Texture,
 }
 
 impl Buffers {
@@ -17,22 +52,13 @@ impl Buffers {
         width: u32,
         height: u32,
     ) -> Self {
-        // Create particle buffer
-        // Each particle is 1 byte (u8)
-        let buffer_size = (initial_particle_grid.len()) as u64;
-        let particle_grid_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Particle Buffer"),
-            size: buffer_size,
-            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        });
+        // Create particle grid texture, one texel per cell holding its material
+        let particle_grid_texture = create_particle_grid_texture(device, width, height);
+        write_particle_grid(queue, &particle_grid_texture, &initial_particle_grid);
 
-        // Write initial particle data to buffer
-        queue.write_buffer(
-            &particle_grid_buffer,
-            0,
-            &initial_particle_grid, // cast not needed, since already &[u8]
-        );
+        // Create particle metadata texture, one texel per cell holding the metadata of its particle.
+        // Textures are zero initialized, the simulation uploads the metadata with its first snapshot.
+        let particle_metadata_texture = create_particle_grid_texture(device, width, height);
 
         // Create grid dimensions uniform buffer
         let grid_dims_buffer = device.create_buffer(&wgpu::BufferDescriptor {
@@ -71,6 +97,172 @@ impl Buffers {
         // Initialize selected material to 1 (sand)
         queue.write_buffer(&selected_material_buffer, 0, bytemuck::cast_slice(&[1u32]));
 
+        // Create brush buffer (radius, shape, mode and replaced material)
+        let brush_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Brush Buffer"),
+            size: 16, // 4 * u32
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        queue.write_buffer(
+            &brush_buffer,
+            0,
+            bytemuck::cast_slice(&Brush::default().to_uniform()),
+        );
+
+        // Create tool preview buffer (kind, point count and the points of the previewed shape)
+        let tool_preview_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Tool Preview Buffer"),
+            size: (4 + MAX_SHAPE_POINTS as u64 * 4) * 4, // header and one vec4<f32> per point
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        // Initialize to previewing the brush, buffers are zero initialized
+        queue.write_buffer(
+            &tool_preview_buffer,
+            0,
+            bytemuck::cast_slice(&[PREVIEW_BRUSH, 0]),
+        );
+
+        // Create palette buffer (swatch size and material count of the palette bar)
+        let palette_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Palette Buffer"),
+            size: 16, // f32 and 3 * u32
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        queue.write_buffer(
+            &palette_buffer,
+            0,
+            bytemuck::cast_slice(&[
+                (PALETTE_SWATCH_SIZE as f32).to_bits(),
+                MATERIALS.len() as u32,
+                0,
+                0,
+            ]),
+        );
+
+        // Create palette texture, the color of every possible cell value in a single row
+        let palette_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Palette Texture"),
+            size: wgpu::Extent3d {
+                width: MAX_MATERIALS as u32,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        // Create font atlas texture, one byte per pixel
+        let (atlas_width, atlas_height, atlas) = font::atlas();
+        let atlas_size = wgpu::Extent3d {
+            width: atlas_width,
+            height: atlas_height,
+            depth_or_array_layers: 1,
+        };
+        let font_atlas_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Font Atlas Texture"),
+            size: atlas_size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        queue.write_texture(
+            font_atlas_texture.as_image_copy(),
+            &atlas,
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(atlas_width),
+                rows_per_image: None,
+            },
+            atlas_size,
+        );
+
+        // Create HUD buffer (columns, rows, text scale and the glyph indices, one byte per character)
+        let hud_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("HUD Buffer"),
+            size: 16 + (HUD_MAX_COLUMNS * HUD_MAX_ROWS) as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        // Initialize to no text, buffers are zero initialized
+        queue.write_buffer(
+            &hud_buffer,
+            0,
+            bytemuck::cast_slice(&[0, 0, (HUD_TEXT_SCALE as f32).to_bits(), 0]),
+        );
+
+        // Create viewport buffer (offset of the grid in the window, pixels per cell)
+        let viewport_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Viewport Buffer"),
+            size: 16, // 4 * f32
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        // Initialize to one pixel per cell, State updates it to the window size
+        queue.write_buffer(
+            &viewport_buffer,
+            0,
+            bytemuck::cast_slice(&[0f32, 0f32, 1f32, 0f32]),
+        );
+
+        // Create camera buffer (top left corner of the visible part of the grid, zoom)
+        let camera_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Camera Buffer"),
+            size: 16, // 4 * f32
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        queue.write_buffer(
+            &camera_buffer,
+            0,
+            bytemuck::cast_slice(&Camera::new(width, height).to_uniform()),
+        );
+
+        // Create theme buffer (background, text and panel color), written along with the palette below
+        let theme_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Theme Buffer"),
+            size: 48, // 3 * vec4<f32>
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        // Create debug overlay textures: the direction each cell's particle moved in during the last tick,
+        // and whether each chunk changed. Both are only uploaded while an overlay shows them.
+        let cell_motion_texture = create_particle_grid_texture(device, width, height);
+        let (chunks_x, chunks_y) = chunk_count(width, height);
+        let chunk_activity_texture = create_particle_grid_texture(device, chunks_x, chunks_y);
+
+        // Create debug overlay buffer (enabled overlays, chunk size)
+        let debug_overlay_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Debug Overlay Buffer"),
+            size: 16, // 4 * u32
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        // Initialize to no overlays
+        queue.write_buffer(
+            &debug_overlay_buffer,
+            0,
+            bytemuck::cast_slice(&[0, DEBUG_CHUNK_SIZE, 0, 0]),
+        );
+
         // Create bind group layout
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("Particle Bind Group Layout"),
@@ -78,10 +270,10 @@ impl Buffers {
                 wgpu::BindGroupLayoutEntry {
                     binding: 0,
                     visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: true },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Uint,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
                     },
                     count: None,
                 },
@@ -115,41 +307,271 @@ impl Buffers {
                     },
                     count: None,
                 },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 6,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 7,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 8,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 9,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 10,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 11,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 12,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Uint,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 13,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 14,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Uint,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 15,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Uint,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 16,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
             ],
         });
 
-        // Create bind group
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        let buffers = Self {
+            particle_grid_texture,
+            particle_metadata_texture,
+            mouse_position_buffer,
+            selected_material_buffer,
+            brush_buffer,
+            tool_preview_buffer,
+            hud_buffer,
+            viewport_buffer,
+            camera_buffer,
+            palette_texture,
+            theme_buffer,
+            cell_motion_texture,
+            chunk_activity_texture,
+            debug_overlay_buffer,
+            bind_group_layout,
+            grid_dims_buffer,
+            palette_buffer,
+            font_atlas_texture,
+        };
+        buffers.update_theme(queue, Theme::default());
+
+        buffers
+    }
+
+    /// Binds the buffers and textures to the slots of shader.wgsl.
+    /// Has to be created again after `resize_grid` replaced the textures.
+    pub fn create_bind_group(&self, device: &wgpu::Device) -> wgpu::BindGroup {
+        let view =
+            |texture: &wgpu::Texture| texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("Particle Bind Group"),
-            layout: &bind_group_layout,
+            layout: &self.bind_group_layout,
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
-                    resource: particle_grid_buffer.as_entire_binding(),
+                    resource: wgpu::BindingResource::TextureView(&view(
+                        &self.particle_grid_texture,
+                    )),
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
-                    resource: grid_dims_buffer.as_entire_binding(),
+                    resource: self.grid_dims_buffer.as_entire_binding(),
                 },
                 wgpu::BindGroupEntry {
                     binding: 2,
-                    resource: mouse_position_buffer.as_entire_binding(),
+                    resource: self.mouse_position_buffer.as_entire_binding(),
                 },
                 wgpu::BindGroupEntry {
                     binding: 3,
-                    resource: selected_material_buffer.as_entire_binding(),
+                    resource: self.selected_material_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: self.brush_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: self.tool_preview_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: self.palette_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: wgpu::BindingResource::TextureView(&view(&self.font_atlas_texture)),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: self.hud_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 9,
+                    resource: self.viewport_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 10,
+                    resource: self.camera_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 11,
+                    resource: wgpu::BindingResource::TextureView(&view(&self.palette_texture)),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 12,
+                    resource: wgpu::BindingResource::TextureView(&view(
+                        &self.particle_metadata_texture,
+                    )),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 13,
+                    resource: self.theme_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 14,
+                    resource: wgpu::BindingResource::TextureView(&view(&self.cell_motion_texture)),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 15,
+                    resource: wgpu::BindingResource::TextureView(&view(
+                        &self.chunk_activity_texture,
+                    )),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 16,
+                    resource: self.debug_overlay_buffer.as_entire_binding(),
                 },
             ],
-        });
+        })
+    }
 
-        Self {
-            particle_grid_buffer,
-            grid_dims_buffer,
-            mouse_position_buffer,
-            selected_material_buffer,
-            bind_group,
-            bind_group_layout,
-        }
+    /// Replaces the textures that have the grid size baked in with empty ones of the new size,
+    /// the simulation uploads the grid with its next snapshot
+    pub fn resize_grid(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        width: u32,
+        height: u32,
+    ) {
+        self.particle_grid_texture = create_particle_grid_texture(device, width, height);
+        self.particle_metadata_texture = create_particle_grid_texture(device, width, height);
+        self.cell_motion_texture = create_particle_grid_texture(device, width, height);
+        let (chunks_x, chunks_y) = chunk_count(width, height);
+        self.chunk_activity_texture = create_particle_grid_texture(device, chunks_x, chunks_y);
+        queue.write_buffer(
+            &self.grid_dims_buffer,
+            0,
+            bytemuck::cast_slice(&[width, height]),
+        );
     }
 
     /// Update the mouse position buffer with normalized coordinates (0.0 to 1.0)
@@ -161,8 +583,91 @@ impl Buffers {
         );
     }
 
-    pub fn update_particle_grid_buffer(&self, queue: &wgpu::Queue, particle_grid: &[u8]) {
-        queue.write_buffer(&self.particle_grid_buffer, 0, particle_grid);
+    pub fn update_viewport_buffer(&self, queue: &wgpu::Queue, viewport: Viewport) {
+        queue.write_buffer(
+            &self.viewport_buffer,
+            0,
+            bytemuck::cast_slice(&viewport.to_uniform()),
+        );
+    }
+
+    pub fn update_camera_buffer(&self, queue: &wgpu::Queue, camera: Camera) {
+        queue.write_buffer(
+            &self.camera_buffer,
+            0,
+            bytemuck::cast_slice(&camera.to_uniform()),
+        );
+    }
+
+    pub fn update_particle_grid_texture(&self, queue: &wgpu::Queue, particle_grid: &[u8]) {
+        write_particle_grid(queue, &self.particle_grid_texture, particle_grid);
+    }
+
+    pub fn update_particle_metadata_texture(&self, queue: &wgpu::Queue, metadata: &[u8]) {
+        write_particle_grid(queue, &self.particle_metadata_texture, metadata);
+    }
+
+    /// Enables the debug overlays given as OVERLAY_* bits
+    pub fn update_debug_overlay_buffer(&self, queue: &wgpu::Queue, overlays: u32) {
+        queue.write_buffer(
+            &self.debug_overlay_buffer,
+            0,
+            bytemuck::cast_slice(&[overlays, DEBUG_CHUNK_SIZE]),
+        );
+    }
+
+    pub fn update_cell_motion_texture(&self, queue: &wgpu::Queue, motion: &[u8]) {
+        write_particle_grid(queue, &self.cell_motion_texture, motion);
+    }
+
+    pub fn update_chunk_activity_texture(&self, queue: &wgpu::Queue, chunk_activity: &[u8]) {
+        write_particle_grid(queue, &self.chunk_activity_texture, chunk_activity);
+    }
+
+    /// Switches the palette to the material colors of the theme, along with the colors around the grid
+    pub fn update_theme(&self, queue: &wgpu::Queue, theme: Theme) {
+        let colors: Vec<_> = MATERIALS
+            .iter()
+            .map(|material| material.color(theme))
+            .collect();
+        self.update_palette_texture(queue, &colors);
+
+        queue.write_buffer(
+            &self.theme_buffer,
+            0,
+            bytemuck::cast_slice(&theme.to_uniform()),
+        );
+    }
+
+    /// Colors cell values by index, values past the given colors repeat the last one.
+    /// The alpha channel holds how much the color varies between particles of the material.
+    pub fn update_palette_texture(&self, queue: &wgpu::Queue, colors: &[[f32; 3]]) {
+        let to_byte = |channel: f32| (channel.clamp(0.0, 1.0) * 255.0).round() as u8;
+
+        let mut palette = [[0u8; 4]; MAX_MATERIALS];
+        for (index, texel) in palette.iter_mut().enumerate() {
+            if let Some(&[r, g, b]) = colors.get(index).or(colors.last()) {
+                let variation = MATERIALS
+                    .get(index)
+                    .map_or(0.0, |material| material.color_variation);
+                *texel = [to_byte(r), to_byte(g), to_byte(b), to_byte(variation)];
+            }
+        }
+
+        queue.write_texture(
+            self.palette_texture.as_image_copy(),
+            palette.as_flattened(),
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(MAX_MATERIALS as u32 * 4),
+                rows_per_image: None,
+            },
+            wgpu::Extent3d {
+                width: MAX_MATERIALS as u32,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+        );
     }
 
     pub fn update_selected_material_buffer(&self, queue: &wgpu::Queue, material: u8) {
@@ -172,4 +677,158 @@ impl Buffers {
             bytemuck::cast_slice(&[material as u32]),
         );
     }
+
+    pub fn update_brush_buffer(&self, queue: &wgpu::Queue, brush: Brush) {
+        queue.write_buffer(
+            &self.brush_buffer,
+            0,
+            bytemuck::cast_slice(&brush.to_uniform()),
+        );
+    }
+
+    /// Update the tool preview buffer with the preview kind and the points of the previewed shape
+    pub fn update_tool_preview_buffer(
+        &self,
+        queue: &wgpu::Queue,
+        kind: u32,
+        points: &[(i32, i32)],
+    ) {
+        let points = &points[..points.len().min(MAX_SHAPE_POINTS)];
+
+        let mut data = vec![0u32; 4 + points.len() * 4];
+        data[0] = kind;
+        data[1] = points.len() as u32;
+        for (i, point) in points.iter().enumerate() {
+            data[4 + i * 4] = (point.0 as f32).to_bits();
+            data[5 + i * 4] = (point.1 as f32).to_bits();
+        }
+
+        queue.write_buffer(&self.tool_preview_buffer, 0, bytemuck::cast_slice(&data));
+    }
+
+    /// Writes the HUD text, lines and characters that don't fit are cut off. No lines hide the HUD.
+    pub fn update_hud_buffer(&self, queue: &wgpu::Queue, lines: &[String]) {
+        let lines = &lines[..lines.len().min(HUD_MAX_ROWS)];
+        let columns = lines
+            .iter()
+            .map(|line| line.chars().count())
+            .max()
+            .unwrap_or(0)
+            .min(HUD_MAX_COLUMNS);
+
+        // Lines are padded with spaces to the same length, so the shader can index them as a grid
+        let mut glyphs = vec![font::glyph_index(' '); columns * lines.len()];
+        for (row, line) in lines.iter().enumerate() {
+            for (column, c) in line.chars().take(columns).enumerate() {
+                glyphs[row * columns + column] = font::glyph_index(c);
+            }
+        }
+        // Uniform buffers are written in whole words
+        glyphs.resize(glyphs.len().next_multiple_of(4), 0);
+
+        queue.write_buffer(
+            &self.hud_buffer,
+            0,
+            bytemuck::cast_slice(&[columns as u32, lines.len() as u32]),
+        );
+        if !glyphs.is_empty() {
+            queue.write_buffer(&self.hud_buffer, 16, &glyphs);
+        }
+    }
+}
+
+/// Creates a texture the particle grid is rendered from, one `R8Uint` texel per cell (or chunk)
+pub fn create_particle_grid_texture(
+    device: &wgpu::Device,
+    width: u32,
+    height: u32,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Particle Grid Texture"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::R8Uint,
+        // Copying out is only needed to check the texture contents
+        usage: wgpu::TextureUsages::TEXTURE_BINDING
+            | wgpu::TextureUsages::COPY_DST
+            | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    })
+}
+
+fn write_particle_grid(queue: &wgpu::Queue, texture: &wgpu::Texture, particle_grid: &[u8]) {
+    let size = texture.size();
+    queue.write_texture(
+        texture.as_image_copy(),
+        particle_grid,
+        wgpu::TexelCopyBufferLayout {
+            offset: 0,
+            bytes_per_row: Some(size.width),
+            rows_per_image: None,
+        },
+        size,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::gpu_context::{read_texture, test_device};
+
+    #[test]
+    fn odd_sized_grids_are_written_row_by_row() -> anyhow::Result<()> {
+        let Some((device, queue)) = test_device() else {
+            return Ok(());
+        };
+
+        // Rows of the texture writes are not padded, so no width needs to be a multiple of anything
+        for (width, height) in [(1, 30), (37, 29), (63, 45), (257, 3)] {
+            let cells = |offset: u32| -> Vec<u8> {
+                (0..width * height)
+                    .map(|index| ((index + offset) % 7) as u8)
+                    .collect()
+            };
+            let buffers = Buffers::new(&device, &queue, cells(0), width, height);
+            assert_eq!(
+                read_texture(&device, &queue, &buffers.particle_grid_texture)?,
+                cells(0),
+                "{}x{} initial grid",
+                width,
+                height
+            );
+
+            buffers.update_particle_grid_texture(&queue, &cells(3));
+            assert_eq!(
+                read_texture(&device, &queue, &buffers.particle_grid_texture)?,
+                cells(3),
+                "{}x{} updated grid",
+                width,
+                height
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn resizing_replaces_the_grid_textures() -> anyhow::Result<()> {
+        let Some((device, queue)) = test_device() else {
+            return Ok(());
+        };
+
+        let mut buffers = Buffers::new(&device, &queue, vec![1; 16 * 16], 16, 16);
+        buffers.resize_grid(&device, &queue, 37, 29);
+        let size = buffers.particle_grid_texture.size();
+        assert_eq!((size.width, size.height), (37, 29));
+        assert_eq!(
+            read_texture(&device, &queue, &buffers.particle_grid_texture)?,
+            vec![0; 37 * 29]
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,68 @@
+use crate::MAX_ZOOM;
+
+/// Which part of the grid is visible inside the viewport.
+///
+/// The zoom is a whole number, so cells stay equally large and square when zooming in.
+/// fs_main in shader.wgsl samples the same sub-rectangle of the grid.
+#[derive(Clone, Copy, Debug)]
+pub struct Camera {
+    /// Top left corner of the visible part in grid cells
+    pub x: f64,
+    pub y: f64,
+    /// 1 shows the whole grid
+    pub zoom: u32,
+
+    grid_width: u32,
+    grid_height: u32,
+}
+
+impl Camera {
+    pub fn new(grid_width: u32, grid_height: u32) -> Self {
+        Self {
+            x: 0.0,
+            y: 0.0,
+            zoom: 1,
+            grid_width,
+            grid_height,
+        }
+    }
+
+    /// Converts a position inside the viewport, in cells of the unzoomed grid, to grid coordinates
+    pub fn view_to_grid(&self, x: f64, y: f64) -> (f64, f64) {
+        (self.x + x / self.zoom as f64, self.y + y / self.zoom as f64)
+    }
+
+    /// Zooms in (positive delta) or out, keeping the grid position at the view position in place
+    pub fn zoom_at(&mut self, delta: i32, (view_x, view_y): (f64, f64)) {
+        let (grid_x, grid_y) = self.view_to_grid(view_x, view_y);
+
+        self.zoom = self.zoom.saturating_add_signed(delta).clamp(1, MAX_ZOOM);
+        self.x = grid_x - view_x / self.zoom as f64;
+        self.y = grid_y - view_y / self.zoom as f64;
+        self.clamp();
+    }
+
+    /// Moves the visible part by the distance in grid cells
+    pub fn pan(&mut self, dx: f64, dy: f64) {
+        self.x += dx;
+        self.y += dy;
+        self.clamp();
+    }
+
+    /// Keeps the visible part inside the grid
+    fn clamp(&mut self) {
+        let zoom = self.zoom as f64;
+        self.x = self
+            .x
+            .clamp(0.0, self.grid_width as f64 - self.grid_width as f64 / zoom);
+        self.y = self.y.clamp(
+            0.0,
+            self.grid_height as f64 - self.grid_height as f64 / zoom,
+        );
+    }
+
+    /// Layout of the camera uniform in shader.wgsl: top left corner, zoom, unused
+    pub fn to_uniform(self) -> [f32; 4] {
+        [self.x as f32, self.y as f32, self.zoom as f32, 0.0]
+    }
+}
@@ -1,10 +1,10 @@
+use anyhow::Context;
+use std::path::Path;
 use std::sync::Arc;
 use winit::window::Window;
 
 /// Handles GPU initialization and context management
 pub struct GpuContext {
-    instance: wgpu::Instance,
-    adapter: wgpu::Adapter,
     pub surface: wgpu::Surface<'static>,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
@@ -71,8 +71,6 @@ impl GpuContext {
         };
 
         Ok(Self {
-            instance,
-            adapter,
             surface,
             device,
             queue,
@@ -81,3 +79,178 @@ impl GpuContext {
         })
     }
 }
+
+/// Requests a device without any window or surface, e.g. to check the simulation backends.
+/// Uses the software fallback adapter if no GPU is available.
+pub async fn request_headless_device() -> anyhow::Result<(wgpu::Device, wgpu::Queue)> {
+    // All backends, since software rasterizers are often only available through OpenGL
+    let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
+        backends: wgpu::Backends::all(),
+        ..Default::default()
+    });
+
+    let adapter = match instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::default(),
+            compatible_surface: None,
+            force_fallback_adapter: false,
+        })
+        .await
+    {
+        Ok(adapter) => adapter,
+        Err(_) => {
+            instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::default(),
+                    compatible_surface: None,
+                    force_fallback_adapter: true,
+                })
+                .await?
+        }
+    };
+    log::info!("Using headless adapter {:?}", adapter.get_info());
+
+    let (device, queue) = adapter
+        .request_device(&wgpu::DeviceDescriptor {
+            label: None,
+            required_features: wgpu::Features::empty(),
+            experimental_features: wgpu::ExperimentalFeatures::disabled(),
+            // Software adapters don't reach the default limits, the largest grid depends on what
+            // the adapter supports
+            required_limits: adapter.limits(),
+            memory_hints: Default::default(),
+            trace: wgpu::Trace::Off,
+        })
+        .await?;
+
+    Ok((device, queue))
+}
+
+/// Renders into a texture instead of a window and reads the pixels back, e.g. for screenshots or
+/// to check the shader output without a display
+pub struct OffscreenContext {
+    pub device: wgpu::Device,
+    pub queue: wgpu::Queue,
+    pub format: wgpu::TextureFormat,
+    texture: wgpu::Texture,
+}
+
+impl OffscreenContext {
+    /// Creates the render texture on a device from `request_headless_device`, it can't be larger
+    /// than the device's texture limit
+    pub fn new(device: wgpu::Device, queue: wgpu::Queue, width: u32, height: u32) -> Self {
+        // sRGB like the window surface, so the pixels come out the way the window shows them
+        let format = wgpu::TextureFormat::Rgba8UnormSrgb;
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Offscreen Render Texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+
+        Self {
+            device,
+            queue,
+            format,
+            texture,
+        }
+    }
+
+    pub fn view(&self) -> wgpu::TextureView {
+        self.texture
+            .create_view(&wgpu::TextureViewDescriptor::default())
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.texture.width(), self.texture.height())
+    }
+
+    /// Waits for the submitted rendering and returns the RGBA pixels row by row
+    pub fn read_pixels(&self) -> anyhow::Result<Vec<u8>> {
+        read_texture(&self.device, &self.queue, &self.texture)
+    }
+
+    /// Saves the submitted rendering as a binary PPM image, which needs no image library to write
+    pub fn save_screenshot(&self, path: &Path) -> anyhow::Result<()> {
+        let (width, height) = self.size();
+        let mut image = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+        image.extend(
+            self.read_pixels()?
+                .chunks_exact(4)
+                .flat_map(|pixel| &pixel[..3]),
+        );
+        std::fs::write(path, image)
+            .with_context(|| format!("Failed to write screenshot {}", path.display()))
+    }
+}
+
+/// Waits for the submitted work and copies the texels of a texture with `COPY_SRC` usage
+/// back to the CPU, row by row without padding
+pub fn read_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+) -> anyhow::Result<Vec<u8>> {
+    let size = texture.size();
+    let texel_bytes = texture.format().block_copy_size(None).unwrap_or(1);
+    let row_bytes = (size.width * texel_bytes) as usize;
+    // Texture copies need whole blocks of rows, the padding is dropped again below
+    let padded_row_bytes = row_bytes.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as usize);
+    let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Texture Readback Buffer"),
+        size: (padded_row_bytes * size.height as usize) as u64,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Texture Readback Encoder"),
+    });
+    encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        wgpu::TexelCopyBufferInfo {
+            buffer: &readback_buffer,
+            layout: wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_row_bytes as u32),
+                rows_per_image: None,
+            },
+        },
+        size,
+    );
+    queue.submit(std::iter::once(encoder.finish()));
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    readback_buffer.map_async(wgpu::MapMode::Read, .., move |result| {
+        let _ = sender.send(result);
+    });
+    device.poll(wgpu::PollType::wait_indefinitely())?;
+    receiver.recv()??;
+
+    let data = readback_buffer.get_mapped_range(..);
+    Ok(data
+        .chunks(padded_row_bytes)
+        .flat_map(|row| &row[..row_bytes])
+        .copied()
+        .collect())
+}
+
+/// Device for the GPU tests, None if no adapter is available on this machine and the test should be skipped
+#[cfg(test)]
+pub fn test_device() -> Option<(wgpu::Device, wgpu::Queue)> {
+    match pollster::block_on(request_headless_device()) {
+        Ok(device) => Some(device),
+        Err(e) => {
+            eprintln!("Skipping GPU test, no adapter: {}", e);
+            None
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use super::particle_manager::{MATERIALS, Theme};
+use super::simulation_thread::SimulationStats;
+use crate::STATS_INTERVAL_MS;
+use std::time::{Duration, Instant};
+
+/// Text overlay showing how fast the app runs, the selected material, the brush and the particle counts
+pub struct Hud {
+    pub visible: bool,
+
+    // Frames rendered since the frame rate was last measured
+    frames: u32,
+    last_fps_update: Instant,
+    fps: f32,
+
+    pub stats: SimulationStats,
+    pub selected_material: u8,
+    pub brush_radius: u32,
+    pub theme: Theme,
+    /// Names of the enabled debug overlays
+    pub debug_overlays: Vec<&'static str>,
+    /// Whether the overlays make the GPU backend read its grid back every tick
+    pub overlays_read_back_grid: bool,
+}
+
+impl Hud {
+    pub fn new() -> Self {
+        Self {
+            visible: true,
+            frames: 0,
+            last_fps_update: Instant::now(),
+            fps: 0.0,
+            stats: SimulationStats::default(),
+            selected_material: 1,
+            brush_radius: 0,
+            theme: Theme::default(),
+            debug_overlays: Vec::new(),
+            overlays_read_back_grid: false,
+        }
+    }
+
+    /// Counts a rendered frame, returns true whenever the frame rate was measured again
+    pub fn count_frame(&mut self) -> bool {
+        self.frames += 1;
+
+        let elapsed = self.last_fps_update.elapsed();
+        if elapsed < Duration::from_millis(STATS_INTERVAL_MS) {
+            return false;
+        }
+
+        self.fps = self.frames as f32 / elapsed.as_secs_f32();
+        self.frames = 0;
+        self.last_fps_update = Instant::now();
+        true
+    }
+
+    pub fn lines(&self, paused: bool) -> Vec<String> {
+        let ticks_per_second = if paused {
+            "paused".to_string()
+        } else {
+            format!("{:.1}", self.stats.ticks_per_second)
+        };
+        let material = MATERIALS
+            .get(self.selected_material as usize)
+            .map_or("?", |material| material.name);
+
+        let mut lines = vec![
+            format!("FPS: {:.1}", self.fps),
+            format!("Ticks/s: {}", ticks_per_second),
+            format!(
+                "Tick time: {:.2} ms",
+                self.stats.tick_time.as_secs_f64() * 1000.0
+            ),
+            format!("Material: {}", material),
+            format!("Brush radius: {}", self.brush_radius),
+            format!("Theme: {}", self.theme.name()),
+        ];
+        for overlay in &self.debug_overlays {
+            lines.push(format!("Overlay: {}", overlay));
+        }
+        if self.overlays_read_back_grid {
+            lines.push("GPU readback: 2 grids per tick".to_string());
+        }
+        for (material, count) in MATERIALS.iter().zip(&self.stats.particle_counts) {
+            lines.push(format!("{}: {}", material.name, count));
+        }
+        lines
+    }
+}
@@ -1,14 +1,79 @@
-use crate::MS_PER_SIMULATION;
-use buffers::Buffers;
-use gpu_context::GpuContext;
-use particle_manager::ParticleManager;
+use crate::config::Config;
+use crate::{
+    MAX_SIMULATION_STEPS_PER_FRAME, MAX_UPDATES_PER_SECOND, MIN_UPDATES_PER_SECOND,
+    PALETTE_SWATCH_SIZE, PIXELS_PER_CELL, STAMP_DIRECTORY,
+};
+use buffers::{
+    Buffers, OVERLAY_METADATA, OVERLAY_MOVE_DIRECTION, OVERLAY_MOVED_CELLS,
+    OVERLAY_UNCHANGED_CHUNKS, PREVIEW_BRUSH, PREVIEW_NOTHING, PREVIEW_PASTE, PREVIEW_SELECTION,
+};
+use camera::Camera;
+use gpu_context::{GpuContext, OffscreenContext};
+use hud::Hud;
+pub use particle_manager::{BackendKind, MAX_SHAPE_POINTS, Scene, Shape, ShapeKind, Theme};
+
+use particle_manager::{CpuBackend, GpuBackend, MATERIALS, ParticleManager, SimulationBackend};
+use simulation_thread::{Command, SimulationThread};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
+use viewport::Viewport;
 use winit::window::Window;
 
 mod buffers;
+mod camera;
+mod font;
 mod gpu_context;
+mod hud;
 mod particle_manager;
+#[cfg(test)]
+mod render_check;
+mod simulation_thread;
+mod viewport;
+
+/// What the overlay shows on top of the grid
+pub enum ToolPreview<'a> {
+    Nothing,
+    Brush,
+    Shape(&'a Shape),
+    /// Outline of the selected rectangle between two corners
+    Selection((i32, i32), (i32, i32)),
+    /// Where the clipboard would be pasted at the cursor
+    Paste,
+}
+
+/// Views of the simulation internals drawn on top of the grid
+#[derive(Clone, Copy)]
+pub enum DebugOverlay {
+    /// Highlights the cells that moved in the last tick
+    MovedCells,
+    /// Dims the chunks in which nothing changed in the last tick
+    UnchangedChunks,
+    /// Colors moved cells by the direction they moved in
+    MoveDirection,
+    /// Shows the metadata of every cell
+    Metadata,
+}
+
+impl DebugOverlay {
+    fn bit(self) -> u32 {
+        match self {
+            DebugOverlay::MovedCells => OVERLAY_MOVED_CELLS,
+            DebugOverlay::UnchangedChunks => OVERLAY_UNCHANGED_CHUNKS,
+            DebugOverlay::MoveDirection => OVERLAY_MOVE_DIRECTION,
+            DebugOverlay::Metadata => OVERLAY_METADATA,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            DebugOverlay::MovedCells => "moved",
+            DebugOverlay::UnchangedChunks => "unchanged chunks",
+            DebugOverlay::MoveDirection => "move direction",
+            DebugOverlay::Metadata => "metadata",
+        }
+    }
+}
 
 pub struct State {
     pub window: Arc<Window>,
@@ -19,125 +84,224 @@ pub struct State {
     render_pipeline: wgpu::RenderPipeline,
 
     buffers: Buffers,
-    particle_manager: ParticleManager,
-
-    last_update: Instant,
-    update_interval: Duration,
+    // Created again whenever the grid is resized, along with the textures it binds
+    bind_group: wgpu::BindGroup,
+    simulation: SimulationThread,
+
+    grid_width: u32,
+    grid_height: u32,
+    // Where the grid is drawn in the window
+    viewport: Viewport,
+    // Which part of the grid is shown in the viewport
+    camera: Camera,
+    // Last cursor position in the window, the brush preview moves with the camera
+    cursor_position: Option<(f64, f64)>,
+
+    paused: bool,
+    updates_per_second: u32,
+    // Colors of the materials and around the grid
+    theme: Theme,
+    // Backend created again when the grid is resized, with the same seed for the particle metadata
+    backend_kind: BackendKind,
+    seed: u64,
+    // Whether the grid is resized along with the window instead of being scaled to it
+    resize_grid_with_window: bool,
+    // Enabled debug overlays as OVERLAY_* bits
+    debug_overlays: u32,
+
+    // The paste preview is redrawn whenever the clipboard changes its size
+    clipboard_size: (u32, u32),
+    previewing_paste: bool,
+    // Index of the stamp file loaded next
+    next_stamp: usize,
+
+    hud: Hud,
 }
 
 impl State {
-    pub async fn new(window: Arc<Window>, width: u32, height: u32) -> anyhow::Result<Self> {
+    pub async fn new(window: Arc<Window>, config: &Config) -> anyhow::Result<Self> {
+        let (width, height) = (config.width, config.height);
+
         // Create gpu context containing the gpu instance, adapter, surface, device, queue, surface format and surface config
         let gpu_context = GpuContext::new(window.clone()).await?;
+        check_grid_size(&gpu_context.device, config.backend, width, height)?;
 
-        let particle_manager = ParticleManager::new(width, height);
-
-        // Create particle buffers and bind group
+        // Create particle buffers and bind group, the grid starts out with the configured scene
+        let cells = config.scene.cells(width, height, config.seed);
         let buffers = Buffers::new(
             &gpu_context.device,
             &gpu_context.queue,
-            particle_manager.particle_grid().to_vec(),
-            particle_manager.width(),
-            particle_manager.height(),
+            cells.clone(),
+            width,
+            height,
         );
+        let viewport = Viewport::new(
+            gpu_context.surface_config.width,
+            gpu_context.surface_config.height,
+            width,
+            height,
+        );
+        buffers.update_viewport_buffer(&gpu_context.queue, viewport);
+        buffers.update_theme(&gpu_context.queue, config.theme);
+
+        let mut backend = create_backend(
+            &gpu_context,
+            &buffers,
+            config.backend,
+            width,
+            height,
+            config.seed,
+        );
+        backend.write_region(0, 0, width, &cells);
+        let mut particle_manager = ParticleManager::new(
+            backend,
+            width,
+            height,
+            config.seed,
+            config.undo_memory_budget,
+        );
+        particle_manager.brush_mut().radius = config.brush_radius;
+        let simulation = SimulationThread::spawn(
+            particle_manager,
+            update_interval(config.updates_per_second),
+            MAX_SIMULATION_STEPS_PER_FRAME,
+        )?;
 
-        // Load shader
-        let shader = gpu_context
-            .device
-            .create_shader_module(wgpu::ShaderModuleDescriptor {
-                label: Some("Particle Shader"),
-                source: wgpu::ShaderSource::Wgsl(include_str!("../shader.wgsl").into()),
-            });
-
-        // Create render pipeline layout
-        let pipeline_layout =
-            gpu_context
-                .device
-                .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                    label: Some("Render Pipeline Layout"),
-                    bind_group_layouts: &[&buffers.bind_group_layout],
-                    immediate_size: 0,
-                });
-
-        // Create render pipeline
-        let render_pipeline =
-            gpu_context
-                .device
-                .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                    label: Some("Render Pipeline"),
-                    layout: Some(&pipeline_layout),
-                    vertex: wgpu::VertexState {
-                        module: &shader,
-                        entry_point: Some("vs_main"),
-                        buffers: &[],
-                        compilation_options: wgpu::PipelineCompilationOptions::default(),
-                    },
-                    fragment: Some(wgpu::FragmentState {
-                        module: &shader,
-                        entry_point: Some("fs_main"),
-                        targets: &[Some(wgpu::ColorTargetState {
-                            format: gpu_context.surface_format,
-                            blend: Some(wgpu::BlendState::REPLACE),
-                            write_mask: wgpu::ColorWrites::ALL,
-                        })],
-                        compilation_options: Default::default(),
-                    }),
-                    // The primitive field describes how to interpret our vertices when converting them into triangles.
-                    primitive: wgpu::PrimitiveState {
-                        // every three vertices will correspond to one triangle
-                        topology: wgpu::PrimitiveTopology::TriangleList,
-                        strip_index_format: None,
-                        front_face: wgpu::FrontFace::Ccw,
-                        cull_mode: None,
-                        polygon_mode: wgpu::PolygonMode::Fill,
-                        unclipped_depth: false,
-                        conservative: false,
-                    },
-                    depth_stencil: None,
-                    multisample: wgpu::MultisampleState {
-                        count: 1,
-                        mask: !0,
-                        alpha_to_coverage_enabled: false,
-                    },
-                    multiview_mask: None,
-                    cache: None,
-                });
+        let render_pipeline = create_render_pipeline(
+            &gpu_context.device,
+            gpu_context.surface_format,
+            &buffers.bind_group_layout,
+        );
+        let bind_group = buffers.create_bind_group(&gpu_context.device);
 
-        Ok(Self {
+        let mut state = Self {
             gpu_context,
             is_surface_configured: false,
             window,
             render_pipeline,
+            bind_group,
             buffers,
-            particle_manager,
-            last_update: Instant::now(),
-            update_interval: Duration::from_millis(MS_PER_SIMULATION),
-        })
+            simulation,
+            grid_width: width,
+            grid_height: height,
+            viewport,
+            camera: Camera::new(width, height),
+            cursor_position: None,
+            paused: false,
+            updates_per_second: config.updates_per_second,
+            theme: config.theme,
+            backend_kind: config.backend,
+            seed: config.seed,
+            resize_grid_with_window: config.resize_grid_with_window,
+            debug_overlays: 0,
+
+            clipboard_size: (0, 0),
+            previewing_paste: false,
+            next_stamp: 0,
+
+            hud: Hud::new(),
+        };
+        state.hud.theme = config.theme;
+        state.update_title();
+
+        Ok(state)
     }
 
     // --- Material Selection ---
+    // The selected material buffer is updated once the simulation thread publishes the change
     pub(crate) fn cycle_material_up(&mut self) {
-        self.particle_manager.cycle_material_up();
-
-        self.buffers.update_selected_material_buffer(
-            &self.gpu_context.queue,
-            self.particle_manager.selected_material(),
-        );
+        self.simulation
+            .edit(|particle_manager| particle_manager.cycle_material_up());
     }
     pub(crate) fn cycle_material_down(&mut self) {
-        self.particle_manager.cycle_material_down();
+        self.simulation
+            .edit(|particle_manager| particle_manager.cycle_material_down());
+    }
 
-        self.buffers.update_selected_material_buffer(
-            &self.gpu_context.queue,
-            self.particle_manager.selected_material(),
-        );
+    pub fn select_material(&mut self, material: u8) {
+        self.simulation
+            .edit(move |particle_manager| particle_manager.select_material(material));
+    }
+
+    /// Material of the palette bar swatch under the cursor, the layout matches palette_swatch_at in shader.wgsl
+    pub fn palette_swatch_at(&self, cursor_x: f64, cursor_y: f64) -> Option<u8> {
+        let size = PALETTE_SWATCH_SIZE as f64;
+        let gap = (size / 4.0).floor();
+        let (x, y) = (cursor_x - gap, cursor_y - gap);
+        let swatch = (x / (size + gap)).floor();
+        if x < 0.0 || y < 0.0 || y >= size || x - swatch * (size + gap) >= size {
+            return None;
+        }
+
+        (swatch < MATERIALS.len() as f64).then_some(swatch as u8)
+    }
+
+    // --- Brush ---
+    // The brush buffer is updated once the simulation thread publishes the change
+    pub fn change_brush_radius(&mut self, delta: i32) {
+        self.simulation
+            .edit(move |particle_manager| particle_manager.brush_mut().change_radius(delta));
+    }
+
+    pub fn change_spray_density(&mut self, delta: f32) {
+        self.simulation
+            .edit(move |particle_manager| particle_manager.brush_mut().change_spray_density(delta));
+    }
+
+    pub fn cycle_brush_shape(&mut self) {
+        self.simulation
+            .edit(|particle_manager| particle_manager.brush_mut().cycle_shape());
+    }
+
+    pub fn cycle_placement_mode(&mut self) {
+        self.simulation
+            .edit(|particle_manager| particle_manager.brush_mut().cycle_mode());
     }
 
     // --- General Settings ---
     pub fn set_simulation_speed(&mut self, updates_per_second: u32) {
-        self.update_interval = Duration::from_secs_f32(1.0 / updates_per_second as f32);
+        self.updates_per_second =
+            updates_per_second.clamp(MIN_UPDATES_PER_SECOND, MAX_UPDATES_PER_SECOND);
+        self.simulation
+            .send(Command::SetUpdateInterval(update_interval(
+                self.updates_per_second,
+            )));
+        self.update_title();
     }
 
+    pub fn speed_up_simulation(&mut self) {
+        self.set_simulation_speed(self.updates_per_second * 2);
+    }
+
+    pub fn slow_down_simulation(&mut self) {
+        self.set_simulation_speed(self.updates_per_second / 2);
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.simulation.send(Command::SetPaused(self.paused));
+        self.update_title();
+    }
+
+    /// Advances the simulation by a single tick, only while paused
+    pub fn step_simulation(&mut self) {
+        if self.paused {
+            self.simulation.send(Command::Step);
+        }
+    }
+
+    /// Shows the simulation state in the window title
+    fn update_title(&self) {
+        let simulation_state = if self.paused {
+            "paused".to_string()
+        } else {
+            format!("{} ticks/s", self.updates_per_second)
+        };
+        self.window
+            .set_title(&format!("Sand Simulation - {}", simulation_state));
+    }
+
+    /// Resizes the surface to the window and, if the grid is resized with the window, the grid as well
     pub fn resize(&mut self, width: u32, height: u32) {
         if width > 0 && height > 0 {
             self.gpu_context.surface_config.width = width;
@@ -146,16 +310,91 @@ impl State {
                 .surface
                 .configure(&self.gpu_context.device, &self.gpu_context.surface_config);
             self.is_surface_configured = true;
+
+            if self.resize_grid_with_window {
+                self.fit_grid_to_window();
+            } else {
+                self.viewport = Viewport::new(width, height, self.grid_width, self.grid_height);
+                self.buffers
+                    .update_viewport_buffer(&self.gpu_context.queue, self.viewport);
+            }
         }
     }
 
-    // --- Mouse position ---
-    pub fn update_mouse_position(&mut self, cursor_x: f64, cursor_y: f64) {
-        let window_size = self.window.inner_size();
+    /// Resizes the grid so that it fills the window with cells of PIXELS_PER_CELL pixels
+    pub fn fit_grid_to_window(&mut self) {
+        let window_width = self.gpu_context.surface_config.width;
+        let window_height = self.gpu_context.surface_config.height;
+
+        self.resize_grid(
+            window_width / PIXELS_PER_CELL,
+            window_height / PIXELS_PER_CELL,
+        );
+    }
+
+    pub fn grid_size(&self) -> (u32, u32) {
+        (self.grid_width, self.grid_height)
+    }
+
+    /// Replaces the grid with one of the given size, keeping its content anchored to the bottom
+    pub fn resize_grid(&mut self, width: u32, height: u32) {
+        let (width, height) = (width.max(1), height.max(1));
+        if (width, height) == (self.grid_width, self.grid_height) {
+            return;
+        }
+        log::info!("Resizing grid to {}x{}", width, height);
+
+        // Only the textures have the grid size baked in, the bind group is the one thing bound to them
+        let device = &self.gpu_context.device;
+        self.buffers
+            .resize_grid(device, &self.gpu_context.queue, width, height);
+        self.bind_group = self.buffers.create_bind_group(device);
+
+        let backend = create_backend(
+            &self.gpu_context,
+            &self.buffers,
+            self.backend_kind,
+            width,
+            height,
+            self.seed,
+        );
+        self.simulation
+            .edit(move |particle_manager| particle_manager.resize(backend, width, height));
+
+        self.grid_width = width;
+        self.grid_height = height;
+        self.viewport = Viewport::new(
+            self.gpu_context.surface_config.width,
+            self.gpu_context.surface_config.height,
+            width,
+            height,
+        );
+        self.camera = Camera::new(width, height);
+
+        // The grid content comes with the next snapshot
+        self.buffers
+            .update_viewport_buffer(&self.gpu_context.queue, self.viewport);
+        self.update_camera();
+        self.update_hud();
+    }
 
-        // Convert to normalized coordinates (0.0 to 1.0)
-        let normalized_x = (cursor_x / window_size.width as f64) as f32;
-        let normalized_y = (cursor_y / window_size.height as f64) as f32;
+    // --- Mouse position ---
+    /// Moves the brush preview to the cursor, None hides it when the cursor left the window
+    pub fn update_mouse_position(&mut self, cursor_position: Option<(f64, f64)>) {
+        self.cursor_position = cursor_position;
+
+        // Convert to normalized coordinates (0.0 to 1.0 inside the grid),
+        // negative coordinates hide the preview no matter where the camera is
+        let (normalized_x, normalized_y) = match cursor_position {
+            Some((cursor_x, cursor_y)) => {
+                let (grid_x, grid_y) = self.window_to_grid(cursor_x, cursor_y);
+                (
+                    (grid_x / self.grid_width as f64) as f32,
+                    (grid_y / self.grid_height as f64) as f32,
+                )
+            }
+            None => (-1.0, -1.0),
+        };
 
         // Update GPU buffer
         self.buffers.update_mouse_position_buffer(
@@ -165,10 +404,298 @@ impl State {
         );
     }
 
+    /// Converts a cursor position in the window to the grid cell under it, which may lie outside of the grid
+    pub fn cursor_to_grid(&self, cursor_x: f64, cursor_y: f64) -> (i32, i32) {
+        let (grid_x, grid_y) = self.window_to_grid(cursor_x, cursor_y);
+
+        (grid_x.floor() as i32, grid_y.floor() as i32)
+    }
+
+    /// Maps a window position through the viewport and the camera to (fractional) grid coordinates
+    fn window_to_grid(&self, x: f64, y: f64) -> (f64, f64) {
+        let (view_x, view_y) = self.viewport.window_to_view(x, y);
+        self.camera.view_to_grid(view_x, view_y)
+    }
+
+    // --- Camera ---
+    /// Zooms in (positive delta) or out around the cursor
+    pub fn zoom_camera(&mut self, delta: i32, (cursor_x, cursor_y): (f64, f64)) {
+        self.camera
+            .zoom_at(delta, self.viewport.window_to_view(cursor_x, cursor_y));
+        self.update_camera();
+    }
+
+    /// Moves the visible part of the grid by the distance in window pixels
+    pub fn pan_camera(&mut self, dx: f64, dy: f64) {
+        let pixels_per_cell = self.viewport.scale * self.camera.zoom as f64;
+        self.camera.pan(dx / pixels_per_cell, dy / pixels_per_cell);
+        self.update_camera();
+    }
+
+    /// Uploads the camera, the cell under the cursor changes with it
+    fn update_camera(&mut self) {
+        self.buffers
+            .update_camera_buffer(&self.gpu_context.queue, self.camera);
+        self.update_mouse_position(self.cursor_position);
+    }
+
+    /// Moves the visible part of the grid by a share of its width and height, e.g. -0.1 for a tenth to the left
+    pub fn pan_camera_by_view(&mut self, share_x: f64, share_y: f64) {
+        let visible_width = self.grid_width as f64 / self.camera.zoom as f64;
+        let visible_height = self.grid_height as f64 / self.camera.zoom as f64;
+        self.camera
+            .pan(share_x * visible_width, share_y * visible_height);
+        self.update_camera();
+    }
+
     // --- Material creation ---
-    pub fn add_material_at_cursor(&mut self, x: f64, y: f64) {
-        self.particle_manager
-            .add_material_at_cursor(&self.window.inner_size(), x, y);
+    /// Paints the selected material along the line between two grid positions
+    pub fn add_material_line(&mut self, from: (i32, i32), to: (i32, i32)) {
+        self.simulation
+            .edit(move |particle_manager| particle_manager.add_material_line(from, to));
+    }
+
+    /// Erases along the line between two grid positions using the current brush
+    pub fn erase_line(&mut self, from: (i32, i32), to: (i32, i32)) {
+        self.simulation
+            .edit(move |particle_manager| particle_manager.erase_line(from, to));
+    }
+
+    /// Draws the shape with the selected material
+    pub fn draw_shape(&mut self, shape: Shape) {
+        self.simulation
+            .edit(move |particle_manager| particle_manager.draw_shape(&shape));
+    }
+
+    pub fn update_tool_preview(&mut self, preview: ToolPreview) {
+        self.previewing_paste = matches!(preview, ToolPreview::Paste);
+        let (kind, points) = match preview {
+            ToolPreview::Nothing => (PREVIEW_NOTHING, Vec::new()),
+            ToolPreview::Brush => (PREVIEW_BRUSH, Vec::new()),
+            ToolPreview::Shape(shape) => (shape.preview_kind(), shape.points.clone()),
+            ToolPreview::Selection(from, to) => (PREVIEW_SELECTION, vec![from, to]),
+            ToolPreview::Paste => {
+                let (width, height) = self.clipboard_size;
+                (PREVIEW_PASTE, vec![(width as i32, height as i32)])
+            }
+        };
+
+        self.buffers
+            .update_tool_preview_buffer(&self.gpu_context.queue, kind, &points);
+    }
+
+    /// Fills the region of equal material at the grid position with the selected material
+    pub fn fill(&mut self, (x, y): (i32, i32)) {
+        self.simulation
+            .edit(move |particle_manager| particle_manager.fill(x, y));
+    }
+
+    // --- History ---
+    /// Ends the current brush stroke so that it is undone as a whole
+    pub fn finish_edit(&mut self) {
+        self.simulation
+            .edit(|particle_manager| particle_manager.finish_edit());
+    }
+
+    /// Reverts the newest edit, keeping what the simulation did since
+    pub fn undo(&mut self) {
+        self.simulation
+            .edit(|particle_manager| particle_manager.undo());
+    }
+
+    pub fn redo(&mut self) {
+        self.simulation
+            .edit(|particle_manager| particle_manager.redo());
+    }
+
+    /// Reverts the newest edit together with everything the simulation did since
+    pub fn rewind(&mut self) {
+        self.simulation
+            .edit(|particle_manager| particle_manager.rewind());
+    }
+
+    // --- Clipboard ---
+    /// Copies the rectangle between the two corners to the clipboard
+    pub fn copy(&mut self, from: (i32, i32), to: (i32, i32)) {
+        self.simulation
+            .edit(move |particle_manager| particle_manager.copy(from, to));
+    }
+
+    /// Copies the rectangle between the two corners to the clipboard and clears it
+    pub fn cut(&mut self, from: (i32, i32), to: (i32, i32)) {
+        self.simulation
+            .edit(move |particle_manager| particle_manager.cut(from, to));
+    }
+
+    /// Pastes the clipboard centered at the grid position
+    pub fn paste(&mut self, position: (i32, i32)) {
+        self.simulation
+            .edit(move |particle_manager| particle_manager.paste(position));
+    }
+
+    pub fn rotate_clipboard(&mut self) {
+        self.simulation
+            .edit(|particle_manager| particle_manager.rotate_clipboard());
+    }
+
+    pub fn flip_clipboard_horizontally(&mut self) {
+        self.simulation
+            .edit(|particle_manager| particle_manager.flip_clipboard_horizontally());
+    }
+
+    pub fn flip_clipboard_vertically(&mut self) {
+        self.simulation
+            .edit(|particle_manager| particle_manager.flip_clipboard_vertically());
+    }
+
+    /// Saves the rectangle between the two corners as a new stamp file in the stamp directory.
+    /// The file name is picked on the simulation thread when the file is created, errors are only logged.
+    pub fn save_stamp(&mut self, from: (i32, i32), to: (i32, i32)) {
+        self.simulation.edit(move |particle_manager| {
+            match particle_manager.save_stamp(from, to, Path::new(STAMP_DIRECTORY)) {
+                Ok(path) => log::info!("Saved stamp {}", path.display()),
+                Err(e) => log::error!("Saving stamp failed: {:#}", e),
+            }
+        });
+    }
+
+    /// Loads the next stamp file of the stamp directory into the clipboard.
+    /// Returns false if there are no stamp files.
+    pub fn load_next_stamp(&mut self) -> bool {
+        let mut paths: Vec<PathBuf> = match std::fs::read_dir(STAMP_DIRECTORY) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| {
+                    path.extension()
+                        .is_some_and(|extension| extension == "stamp")
+                })
+                .collect(),
+            Err(e) => {
+                log::error!("Reading stamp directory {} failed: {}", STAMP_DIRECTORY, e);
+                return false;
+            }
+        };
+        if paths.is_empty() {
+            log::warn!("No stamps found in {}", STAMP_DIRECTORY);
+            return false;
+        }
+        paths.sort();
+
+        let path = paths.swap_remove(self.next_stamp % paths.len());
+        self.next_stamp += 1;
+        self.simulation.edit(
+            move |particle_manager| match particle_manager.load_stamp(&path) {
+                Ok(_) => log::info!("Loaded stamp {}", path.display()),
+                Err(e) => log::error!("Loading stamp failed: {:#}", e),
+            },
+        );
+        true
+    }
+
+    /// Selects the material at the grid position
+    pub fn pick_material(&mut self, (x, y): (i32, i32)) {
+        self.simulation
+            .edit(move |particle_manager| particle_manager.pick_material(x, y));
+    }
+
+    // --- Simulation ---
+    /// Uploads the latest snapshot published by the simulation thread, returns false if there was none
+    fn sync_simulation(&mut self) -> bool {
+        let Some(snapshot) = self.simulation.new_snapshot() else {
+            return false;
+        };
+
+        // Update GPU textures with updated particle grid and metadata, unless the backend already did.
+        // Grids published before a resize don't fit into the new textures and are skipped.
+        if !snapshot.particle_grid.is_empty()
+            && snapshot.grid_size == (self.grid_width, self.grid_height)
+        {
+            self.buffers
+                .update_particle_grid_texture(&self.gpu_context.queue, &snapshot.particle_grid);
+            self.buffers.update_particle_metadata_texture(
+                &self.gpu_context.queue,
+                &snapshot.particle_metadata,
+            );
+        }
+        if !snapshot.motion.is_empty() && snapshot.grid_size == (self.grid_width, self.grid_height)
+        {
+            let queue = &self.gpu_context.queue;
+            self.buffers
+                .update_cell_motion_texture(queue, &snapshot.motion);
+            self.buffers
+                .update_chunk_activity_texture(queue, &snapshot.chunk_activity);
+        }
+
+        self.buffers
+            .update_selected_material_buffer(&self.gpu_context.queue, snapshot.selected_material);
+        self.buffers
+            .update_brush_buffer(&self.gpu_context.queue, snapshot.brush);
+
+        self.hud.stats.clone_from(&snapshot.stats);
+        self.hud.selected_material = snapshot.selected_material;
+        self.hud.brush_radius = snapshot.brush.radius;
+
+        let clipboard_size = snapshot.clipboard_size;
+        if clipboard_size != self.clipboard_size {
+            self.clipboard_size = clipboard_size;
+            if self.previewing_paste {
+                self.update_tool_preview(ToolPreview::Paste);
+            }
+        }
+        true
+    }
+
+    // --- HUD ---
+    /// Switches to the next color theme
+    pub fn cycle_theme(&mut self) {
+        self.theme = self.theme.next();
+        self.hud.theme = self.theme;
+        self.buffers
+            .update_theme(&self.gpu_context.queue, self.theme);
+        self.update_hud();
+    }
+
+    /// Shows or hides a debug overlay. The simulation only tracks motion while an overlay shows it,
+    /// which with the GPU backend means reading the whole grid back before and after every tick.
+    /// The HUD says so, since it slows the simulation down noticeably.
+    pub fn toggle_debug_overlay(&mut self, overlay: DebugOverlay) {
+        self.debug_overlays ^= overlay.bit();
+        self.buffers
+            .update_debug_overlay_buffer(&self.gpu_context.queue, self.debug_overlays);
+
+        let track_motion = self.debug_overlays
+            & (OVERLAY_MOVED_CELLS | OVERLAY_UNCHANGED_CHUNKS | OVERLAY_MOVE_DIRECTION)
+            != 0;
+        self.simulation
+            .edit(move |particle_manager| particle_manager.set_motion_tracking(track_motion));
+
+        self.hud.debug_overlays = [
+            DebugOverlay::MovedCells,
+            DebugOverlay::UnchangedChunks,
+            DebugOverlay::MoveDirection,
+            DebugOverlay::Metadata,
+        ]
+        .into_iter()
+        .filter(|overlay| self.debug_overlays & overlay.bit() != 0)
+        .map(DebugOverlay::name)
+        .collect();
+        self.hud.overlays_read_back_grid = track_motion && self.backend_kind == BackendKind::Gpu;
+        self.update_hud();
+    }
+
+    pub fn toggle_hud(&mut self) {
+        self.hud.visible = !self.hud.visible;
+        self.update_hud();
+    }
+
+    fn update_hud(&mut self) {
+        let lines = if self.hud.visible {
+            self.hud.lines(self.paused)
+        } else {
+            Vec::new()
+        };
+        self.buffers
+            .update_hud_buffer(&self.gpu_context.queue, &lines);
     }
 
     // --- Render ---
@@ -178,18 +705,9 @@ impl State {
             return Ok(());
         }
 
-        // Only simulate if enough time has passed
-        let now = Instant::now();
-        if now.duration_since(self.last_update) >= self.update_interval {
-            self.particle_manager.simulate_particles();
-
-            // Update GPU buffer with updated particle grid
-            self.buffers.update_particle_grid_buffer(
-                &self.gpu_context.queue,
-                self.particle_manager.particle_grid(),
-            );
-
-            self.last_update = now;
+        let synced = self.sync_simulation();
+        if self.hud.count_frame() || synced {
+            self.update_hud();
         }
 
         // Get the current surface texture
@@ -205,35 +723,13 @@ impl State {
                 .create_command_encoder(&wgpu::CommandEncoderDescriptor {
                     label: Some("Render Encoder"),
                 });
-
-        // Create render pass
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    depth_slice: None,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.0,
-                            g: 0.0,
-                            b: 0.0,
-                            a: 1.0,
-                        }),
-                        store: wgpu::StoreOp::Store,
-                    },
-                })],
-                depth_stencil_attachment: None,
-                timestamp_writes: None,
-                occlusion_query_set: None,
-                multiview_mask: None,
-            });
-
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_bind_group(0, &self.buffers.bind_group, &[]);
-            render_pass.draw(0..3, 0..1); // Draw full-screen triangle
-        }
+        encode_render_pass(
+            &mut encoder,
+            &view,
+            &self.render_pipeline,
+            &self.bind_group,
+            self.theme,
+        );
 
         // Submit commands and present
         self.gpu_context
@@ -244,3 +740,268 @@ impl State {
         Ok(())
     }
 }
+
+/// Simulates the configured scene for a number of ticks without a window and prints the particle counts
+pub async fn run_headless(config: &Config, ticks: u32) -> anyhow::Result<()> {
+    let (width, height) = (config.width, config.height);
+
+    // Only the GPU backend and screenshots need a device, the grid is checked before simulating
+    let device = if config.backend == BackendKind::Gpu || config.screenshot.is_some() {
+        let (device, queue) = gpu_context::request_headless_device().await?;
+        check_grid_size(&device, config.backend, width, height)?;
+        Some((device, queue))
+    } else {
+        None
+    };
+
+    let mut backend: Box<dyn SimulationBackend + Send> = match &device {
+        Some((device, queue)) if config.backend == BackendKind::Gpu => {
+            // Nothing is drawn, the render textures only have to exist for the GPU backend to write into
+            let render_texture = buffers::create_particle_grid_texture(device, width, height);
+            let metadata_texture = buffers::create_particle_grid_texture(device, width, height);
+            Box::new(GpuBackend::new(
+                device,
+                queue,
+                &render_texture,
+                &metadata_texture,
+                width,
+                height,
+                config.seed,
+            ))
+        }
+        _ => Box::new(CpuBackend::new(width, height, config.seed)),
+    };
+
+    backend.write_region(0, 0, width, &config.scene.cells(width, height, config.seed));
+    let mut particle_manager = ParticleManager::new(
+        backend,
+        width,
+        height,
+        config.seed,
+        config.undo_memory_budget,
+    );
+
+    let start = Instant::now();
+    for _ in 0..ticks {
+        particle_manager.simulate_particles();
+    }
+    // Reading the grid waits for a GPU backend to finish its ticks
+    let counts = particle_manager.particle_counts();
+    let elapsed = start.elapsed();
+
+    println!(
+        "Simulated {} ticks of a {}x{} grid in {:.2} s ({:.3} ms per tick)",
+        ticks,
+        width,
+        height,
+        elapsed.as_secs_f64(),
+        elapsed.as_secs_f64() * 1000.0 / ticks.max(1) as f64
+    );
+    for (material, count) in MATERIALS.iter().zip(counts) {
+        println!("{}: {}", material.name, count);
+    }
+
+    if let (Some(path), Some((device, queue))) = (&config.screenshot, device) {
+        // Cells are as large as in the window unless the image would exceed the texture limit
+        let pixels_per_cell =
+            PIXELS_PER_CELL.min(device.limits().max_texture_dimension_2d / width.max(height));
+        let context = OffscreenContext::new(
+            device,
+            queue,
+            width * pixels_per_cell,
+            height * pixels_per_cell,
+        );
+        let particles = particle_manager.particle_grid().to_vec();
+        let metadata = particle_manager.particle_metadata();
+        render_offscreen(&context, particles, metadata, width, height, config.theme);
+        context.save_screenshot(path)?;
+        println!("Saved screenshot {}", path.display());
+    }
+
+    Ok(())
+}
+
+/// Renders a grid into the offscreen texture the way the window shows it and returns where the
+/// grid was placed
+fn render_offscreen(
+    context: &OffscreenContext,
+    particles: Vec<u8>,
+    metadata: &[u8],
+    width: u32,
+    height: u32,
+    theme: Theme,
+) -> Viewport {
+    let buffers = Buffers::new(&context.device, &context.queue, particles, width, height);
+    buffers.update_particle_metadata_texture(&context.queue, metadata);
+    buffers.update_theme(&context.queue, theme);
+    let (render_width, render_height) = context.size();
+    let viewport = Viewport::new(render_width, render_height, width, height);
+    buffers.update_viewport_buffer(&context.queue, viewport);
+    let render_pipeline =
+        create_render_pipeline(&context.device, context.format, &buffers.bind_group_layout);
+    let bind_group = buffers.create_bind_group(&context.device);
+
+    let mut encoder = context
+        .device
+        .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Offscreen Render Encoder"),
+        });
+    encode_render_pass(
+        &mut encoder,
+        &context.view(),
+        &render_pipeline,
+        &bind_group,
+        theme,
+    );
+    context.queue.submit(std::iter::once(encoder.finish()));
+    viewport
+}
+
+/// Fails if the device can't hold the grid textures, and for the GPU backend its buffers, of a
+/// grid of the size
+fn check_grid_size(
+    device: &wgpu::Device,
+    kind: BackendKind,
+    width: u32,
+    height: u32,
+) -> anyhow::Result<()> {
+    let limits = device.limits();
+    let max_size = limits.max_texture_dimension_2d;
+    if width > max_size || height > max_size {
+        anyhow::bail!(
+            "The grid can be at most {} cells wide and high on this device, got {}x{}",
+            max_size,
+            width,
+            height
+        );
+    }
+    if kind == BackendKind::Gpu {
+        GpuBackend::check_limits(&limits, width, height)?;
+    }
+    Ok(())
+}
+
+/// The CPU backend is the reference implementation, the GPU backend renders its grid directly into the particle grid texture
+fn create_backend(
+    gpu_context: &GpuContext,
+    buffers: &Buffers,
+    kind: BackendKind,
+    width: u32,
+    height: u32,
+    seed: u64,
+) -> Box<dyn SimulationBackend + Send> {
+    if kind == BackendKind::Gpu {
+        Box::new(GpuBackend::new(
+            &gpu_context.device,
+            &gpu_context.queue,
+            &buffers.particle_grid_texture,
+            &buffers.particle_metadata_texture,
+            width,
+            height,
+            seed,
+        ))
+    } else {
+        Box::new(CpuBackend::new(width, height, seed))
+    }
+}
+
+/// Draws the grid, the palette bar and the HUD into the view, the window and offscreen rendering share this
+fn encode_render_pass(
+    encoder: &mut wgpu::CommandEncoder,
+    view: &wgpu::TextureView,
+    render_pipeline: &wgpu::RenderPipeline,
+    bind_group: &wgpu::BindGroup,
+    theme: Theme,
+) {
+    let [background_r, background_g, background_b] = theme.background();
+
+    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some("Render Pass"),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view,
+            depth_slice: None,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color {
+                    r: background_r as f64,
+                    g: background_g as f64,
+                    b: background_b as f64,
+                    a: 1.0,
+                }),
+                store: wgpu::StoreOp::Store,
+            },
+        })],
+        depth_stencil_attachment: None,
+        timestamp_writes: None,
+        occlusion_query_set: None,
+        multiview_mask: None,
+    });
+
+    render_pass.set_pipeline(render_pipeline);
+    render_pass.set_bind_group(0, bind_group, &[]);
+    render_pass.draw(0..3, 0..1); // Draw full-screen triangle
+}
+
+fn create_render_pipeline(
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+    bind_group_layout: &wgpu::BindGroupLayout,
+) -> wgpu::RenderPipeline {
+    // Load shader
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Particle Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("../shader.wgsl").into()),
+    });
+
+    // Create render pipeline layout
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Render Pipeline Layout"),
+        bind_group_layouts: &[bind_group_layout],
+        immediate_size: 0,
+    });
+
+    // Create render pipeline
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Render Pipeline"),
+        layout: Some(&pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: Some("vs_main"),
+            buffers: &[],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: Some("fs_main"),
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: Default::default(),
+        }),
+        // The primitive field describes how to interpret our vertices when converting them into triangles.
+        primitive: wgpu::PrimitiveState {
+            // every three vertices will correspond to one triangle
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: None,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview_mask: None,
+        cache: None,
+    })
+}
+
+fn update_interval(updates_per_second: u32) -> Duration {
+    Duration::from_secs_f32(1.0 / updates_per_second as f32)
+}
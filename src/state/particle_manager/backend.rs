@@ -0,0 +1,109 @@
+use super::random::Rng;
+
+/// A simulation backend owns the particle grid and advances it one tick at a time.
+///
+/// The CPU backend running `simulate_particles` is the reference implementation,
+/// every other backend has to follow the same material rules.
+pub trait SimulationBackend {
+    /// Advances the simulation by one tick
+    fn step(&mut self);
+
+    /// Returns the particle grid (one byte per cell, row by row)
+    fn read_grid(&mut self) -> &[u8];
+
+    /// Returns one byte of metadata per cell (row by row), which moves along with the particle in the cell.
+    /// It is picked at random when the particle is placed and varies its color.
+    fn read_metadata(&mut self) -> &[u8];
+
+    /// Overwrites the rectangle starting at (x, y) with `cells`, which contains `width` cells per row.
+    /// Cells whose material changes hold a new particle and get new metadata.
+    fn write_region(&mut self, x: u32, y: u32, width: u32, cells: &[u8]);
+
+    /// Overwrites the rectangle like `write_region`, but gives every cell the metadata in `metadata`,
+    /// e.g. to bring back particles exactly as they were
+    fn write_region_with_metadata(
+        &mut self,
+        x: u32,
+        y: u32,
+        width: u32,
+        cells: &[u8],
+        metadata: &[u8],
+    );
+
+    /// Whether the backend writes the particle grid and metadata textures of the render pass itself.
+    /// Otherwise the grid has to be uploaded after it changed.
+    fn renders_directly(&self) -> bool {
+        false
+    }
+}
+
+/// Particles and their metadata row by row. The CPU backend steps them directly and the GPU backend
+/// keeps them as its copy of the grid, so both write cells the same way.
+pub struct ParticleGrid {
+    pub particles: Vec<u8>,
+    pub metadata: Vec<u8>,
+    width: u32,
+    // Metadata of newly placed particles
+    rng: Rng,
+}
+
+impl ParticleGrid {
+    /// `seed` seeds the metadata of placed particles
+    pub fn new(width: u32, height: u32, seed: u64) -> Self {
+        let cell_count = width as usize * height as usize;
+        Self {
+            particles: vec![0; cell_count],
+            metadata: vec![0; cell_count],
+            width,
+            rng: Rng::new(seed),
+        }
+    }
+
+    /// Writes the rectangle for `write_region` and `write_region_with_metadata`.
+    /// Without `new_metadata` cells whose material changes get new metadata.
+    pub fn write(&mut self, x: u32, y: u32, width: u32, cells: &[u8], new_metadata: Option<&[u8]>) {
+        for (row, row_cells) in cells.chunks(width as usize).enumerate() {
+            let start = (y as usize + row) * self.width as usize + x as usize;
+            let end = start + row_cells.len();
+            let row_start = row * width as usize;
+
+            let particles = self.particles[start..end].iter_mut();
+            let metadata = self.metadata[start..end].iter_mut();
+            for (i, ((particle, metadata), &new)) in
+                particles.zip(metadata).zip(row_cells).enumerate()
+            {
+                match new_metadata {
+                    Some(new_metadata) => *metadata = new_metadata[row_start + i],
+                    None if *particle != new => *metadata = self.rng.next_u32() as u8,
+                    None => {}
+                }
+                *particle = new;
+            }
+        }
+    }
+}
+
+/// Which backend steps the simulation
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BackendKind {
+    /// `CpuBackend`, the reference implementation
+    #[default]
+    Cpu,
+    /// `GpuBackend`, steps the simulation with a compute shader and renders its grid directly
+    Gpu,
+}
+
+impl BackendKind {
+    pub const ALL: [BackendKind; 2] = [BackendKind::Cpu, BackendKind::Gpu];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BackendKind::Cpu => "cpu",
+            BackendKind::Gpu => "gpu",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+}
@@ -0,0 +1,125 @@
+use super::MATERIALS;
+use crate::{MAX_BRUSH_RADIUS, MIN_BRUSH_RADIUS, RADIUS_ADD_PARTICLES};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BrushShape {
+    Circle,
+    Square,
+    /// Horizontal line, one cell thick
+    HorizontalLine,
+    /// Circle that only fills a random part of its cells
+    Spray,
+}
+
+/// Decides which cells covered by the brush are painted
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PlacementMode {
+    Overwrite,
+    OnlyIntoAir,
+    /// Only replaces cells of the given material
+    Replace(u8),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Brush {
+    pub shape: BrushShape,
+    pub mode: PlacementMode,
+    /// Radius in grid cells
+    pub radius: u32,
+    /// Share of cells painted by the spray (0.0 to 1.0)
+    pub spray_density: f32,
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Self {
+            shape: BrushShape::Circle,
+            mode: PlacementMode::Overwrite,
+            radius: RADIUS_ADD_PARTICLES,
+            spray_density: 0.1,
+        }
+    }
+}
+
+impl Brush {
+    pub fn change_radius(&mut self, delta: i32) {
+        self.radius = self
+            .radius
+            .saturating_add_signed(delta)
+            .clamp(MIN_BRUSH_RADIUS, MAX_BRUSH_RADIUS);
+    }
+
+    pub fn change_spray_density(&mut self, delta: f32) {
+        self.spray_density = (self.spray_density + delta).clamp(0.01, 1.0);
+    }
+
+    pub fn cycle_shape(&mut self) {
+        // Circle -> Square -> horizontal line -> Spray -> Circle
+        self.shape = match self.shape {
+            BrushShape::Circle => BrushShape::Square,
+            BrushShape::Square => BrushShape::HorizontalLine,
+            BrushShape::HorizontalLine => BrushShape::Spray,
+            BrushShape::Spray => BrushShape::Circle,
+        };
+    }
+
+    pub fn cycle_mode(&mut self) {
+        // Overwrite -> only into air -> replace each material but air in turn -> overwrite
+        self.mode = match self.mode {
+            PlacementMode::Overwrite => PlacementMode::OnlyIntoAir,
+            PlacementMode::OnlyIntoAir => PlacementMode::Replace(1),
+            PlacementMode::Replace(material) if (material as usize) < MATERIALS.len() - 1 => {
+                PlacementMode::Replace(material + 1)
+            }
+            PlacementMode::Replace(_) => PlacementMode::Overwrite,
+        };
+    }
+
+    /// How far the brush reaches to the left and right of its center in the row at offset `dy`,
+    /// None if it doesn't cover that row. Every brush shape covers one contiguous run of cells per row.
+    pub fn row_reach(&self, dy: i32) -> Option<i32> {
+        let radius = self.radius as i32;
+        if dy.abs() > radius {
+            return None;
+        }
+
+        match self.shape {
+            BrushShape::Circle | BrushShape::Spray => {
+                Some(((radius * radius - dy * dy) as f32).sqrt() as i32)
+            }
+            BrushShape::Square => Some(radius),
+            BrushShape::HorizontalLine => (dy == 0).then_some(radius),
+        }
+    }
+
+    /// Whether a covered cell containing `cell` is painted with `material`
+    pub fn places_into(&self, cell: u8) -> bool {
+        match self.mode {
+            PlacementMode::Overwrite => true,
+            PlacementMode::OnlyIntoAir => cell == 0,
+            PlacementMode::Replace(material) => cell == material,
+        }
+    }
+
+    /// Layout of the brush uniform in shader.wgsl: radius, shape, mode, replaced material
+    pub fn to_uniform(self) -> [u32; 4] {
+        let shape = match self.shape {
+            BrushShape::Circle => 0,
+            BrushShape::Square => 1,
+            BrushShape::HorizontalLine => 2,
+            BrushShape::Spray => 3,
+        };
+        let (mode, replaced_material) = match self.mode {
+            PlacementMode::Overwrite => (0, 0),
+            PlacementMode::OnlyIntoAir => (1, 0),
+            PlacementMode::Replace(material) => (2, material as u32),
+        };
+
+        [
+            (self.radius as f32).to_bits(),
+            shape,
+            mode,
+            replaced_material,
+        ]
+    }
+}
@@ -0,0 +1,471 @@
+use super::backend::SimulationBackend;
+use super::cpu_backend::CpuBackend;
+use super::gpu_backend::GpuBackend;
+use super::random::Rng;
+use super::simulate::density;
+use crate::state::buffers::create_particle_grid_texture;
+use crate::state::gpu_context::{read_texture, test_device};
+use anyhow::bail;
+
+// Upper bound of ticks a scene may take to come to rest
+const MAX_SETTLE_TICKS: u32 = 2000;
+// A backend counts as settled after the grid stayed the same for this many ticks.
+// Four ticks cover all block offsets of the GPU backend.
+const SETTLED_AFTER_TICKS: u32 = 4;
+// Ticks the water scenes run for, enough for the water to level out
+const WATER_TICKS: u32 = 1000;
+
+/// A seeded scene every backend is run through
+struct Scene {
+    name: &'static str,
+    width: u32,
+    height: u32,
+    seed: u64,
+    // Returns the material of the cell at (x, y)
+    fill: fn(&mut Rng, u32, u32, u32, u32) -> u8,
+    // Whether the material rules only allow one outcome, in which case all backends have to end up
+    // with the exact same rest state (true as long as no sand can slide), or in water scenes with
+    // the same particles in every row (true as long as the water lands on a flat floor)
+    unique_outcome: bool,
+}
+
+const SCENES: &[Scene] = &[
+    Scene {
+        name: "stone rain",
+        width: 64,
+        height: 48,
+        seed: 1,
+        fill: |rng, _, y, _, height| (y < height / 2 && rng.below(3) == 0) as u8 * 2,
+        unique_outcome: true,
+    },
+    Scene {
+        name: "sand block",
+        width: 80,
+        height: 60,
+        seed: 2,
+        fill: |_, x, y, width, _| (y < 20 && x >= width / 2 - 10 && x < width / 2 + 10) as u8,
+        unique_outcome: false,
+    },
+    Scene {
+        name: "mixed noise",
+        width: 96,
+        height: 64,
+        seed: 3,
+        fill: |rng, _, _, _, _| [0, 0, 1, 2][rng.below(4) as usize],
+        unique_outcome: false,
+    },
+    Scene {
+        name: "sand on stone ledges",
+        width: 100,
+        height: 80,
+        seed: 4,
+        fill: |rng, x, y, _, _| {
+            if y % 20 == 19 && x % 30 < 15 {
+                2
+            } else if y < 10 {
+                rng.below(2) as u8
+            } else {
+                0
+            }
+        },
+        unique_outcome: false,
+    },
+    // Cell counts that aren't whole words leave a partially filled last word in the render buffer
+    Scene {
+        name: "odd sized stone rain",
+        width: 63,
+        height: 45,
+        seed: 5,
+        fill: |rng, _, y, _, height| (y < height / 2 && rng.below(3) == 0) as u8 * 2,
+        unique_outcome: true,
+    },
+    Scene {
+        name: "odd sized sand block",
+        width: 37,
+        height: 29,
+        seed: 6,
+        fill: |_, x, y, width, _| (y < 10 && x >= width / 2 - 5 && x < width / 2 + 5) as u8,
+        unique_outcome: false,
+    },
+    Scene {
+        name: "single column",
+        width: 1,
+        height: 30,
+        seed: 7,
+        fill: |rng, _, y, _, _| if y < 15 { rng.below(3) as u8 } else { 0 },
+        unique_outcome: true,
+    },
+];
+
+/// Scenes with water, which keeps flowing instead of coming to rest
+const WATER_SCENES: &[Scene] = &[
+    Scene {
+        name: "water drops",
+        width: 48,
+        height: 32,
+        seed: 8,
+        fill: |rng, _, y, _, height| (y < height / 2 && rng.below(4) == 0) as u8 * 3,
+        unique_outcome: true,
+    },
+    Scene {
+        name: "sand rain into a pool",
+        width: 64,
+        height: 40,
+        seed: 9,
+        fill: |rng, _, y, _, height| {
+            if y >= height - 15 {
+                3
+            } else if y < 10 {
+                rng.below(2) as u8
+            } else {
+                0
+            }
+        },
+        unique_outcome: false,
+    },
+    Scene {
+        name: "mixed noise with water",
+        width: 80,
+        height: 48,
+        seed: 10,
+        fill: |rng, _, _, _, _| [0, 0, 1, 2, 3][rng.below(5) as usize],
+        unique_outcome: false,
+    },
+    Scene {
+        name: "odd sized water drops",
+        width: 37,
+        height: 29,
+        seed: 11,
+        fill: |rng, _, y, _, height| (y < height / 2 && rng.below(4) == 0) as u8 * 3,
+        unique_outcome: true,
+    },
+];
+
+/// Runs every scene through the backends created by `create_backend` and compares them against the CPU reference.
+///
+/// Backends may move particles in a different order, so they are compared by what has to hold for all of them:
+/// - no particle is created or destroyed in any tick
+/// - the scene comes to rest, and the rest state is stable under the reference rules
+/// - if the rules only allow a single rest state, the grids are identical
+fn check_backend(
+    backend_name: &str,
+    create_backend: &mut dyn FnMut(u32, u32) -> Box<dyn SimulationBackend>,
+) -> anyhow::Result<()> {
+    for scene in SCENES {
+        let initial_grid = scene_grid(scene);
+        let expected_counts = material_counts(&initial_grid);
+
+        let mut reference = CpuBackend::new(scene.width, scene.height, scene.seed);
+        reference.write_region(0, 0, scene.width, &initial_grid);
+        let reference_grid = settle(&mut reference, &expected_counts)?;
+
+        let mut backend = create_backend(scene.width, scene.height);
+        backend.write_region(0, 0, scene.width, &initial_grid);
+        let grid = match settle(backend.as_mut(), &expected_counts) {
+            Ok(grid) => grid,
+            Err(e) => bail!("{} diverged in scene '{}': {}", backend_name, scene.name, e),
+        };
+
+        // A rest state of any backend has to be a rest state of the reference as well
+        let mut check = CpuBackend::new(scene.width, scene.height, scene.seed);
+        check.write_region(0, 0, scene.width, &grid);
+        check.step();
+        if check.read_grid() != grid.as_slice() {
+            bail!(
+                "{} came to rest in scene '{}' in a state the reference would still move",
+                backend_name,
+                scene.name
+            );
+        }
+
+        if scene.unique_outcome && grid != reference_grid {
+            bail!(
+                "{} came to rest in scene '{}' in a different state than the reference",
+                backend_name,
+                scene.name
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs every water scene through the backends created by `create_backend` and compares them against the CPU reference.
+///
+/// Water never comes to rest, so instead of rest states the backends are compared by:
+/// - no particle is created or destroyed in any tick
+/// - after WATER_TICKS ticks no particle sits directly above a lighter one
+/// - if the scene only allows one outcome, every row holds the same particles as in the reference
+fn check_water_scenes(
+    backend_name: &str,
+    create_backend: &mut dyn FnMut(u32, u32) -> Box<dyn SimulationBackend>,
+) -> anyhow::Result<()> {
+    for scene in WATER_SCENES {
+        let initial_grid = scene_grid(scene);
+        let expected_counts = material_counts(&initial_grid);
+
+        let mut reference = CpuBackend::new(scene.width, scene.height, scene.seed);
+        reference.write_region(0, 0, scene.width, &initial_grid);
+        let reference_grid = run_water(&mut reference, scene.width, &expected_counts)?;
+
+        let mut backend = create_backend(scene.width, scene.height);
+        backend.write_region(0, 0, scene.width, &initial_grid);
+        let grid = match run_water(backend.as_mut(), scene.width, &expected_counts) {
+            Ok(grid) => grid,
+            Err(e) => bail!("{} diverged in scene '{}': {}", backend_name, scene.name, e),
+        };
+
+        let row_counts = |grid: &[u8]| -> Vec<_> {
+            grid.chunks(scene.width as usize)
+                .map(material_counts)
+                .collect()
+        };
+        if scene.unique_outcome && row_counts(&grid) != row_counts(&reference_grid) {
+            bail!(
+                "{} filled the rows differently than the reference in scene '{}'",
+                backend_name,
+                scene.name
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn scene_grid(scene: &Scene) -> Vec<u8> {
+    let mut rng = Rng::new(scene.seed);
+
+    let mut grid = Vec::with_capacity(scene.width as usize * scene.height as usize);
+    for y in 0..scene.height {
+        for x in 0..scene.width {
+            grid.push((scene.fill)(&mut rng, x, y, scene.width, scene.height));
+        }
+    }
+
+    grid
+}
+
+fn material_counts(grid: &[u8]) -> [usize; 256] {
+    let mut counts = [0; 256];
+    for &cell in grid {
+        counts[cell as usize] += 1;
+    }
+    counts
+}
+
+/// Steps the backend until the grid stops changing and returns the rest state
+fn settle(
+    backend: &mut dyn SimulationBackend,
+    expected_counts: &[usize; 256],
+) -> anyhow::Result<Vec<u8>> {
+    let mut previous = backend.read_grid().to_vec();
+    let mut unchanged_ticks = 0;
+
+    for tick in 0..MAX_SETTLE_TICKS {
+        backend.step();
+        let grid = backend.read_grid();
+
+        if material_counts(grid) != *expected_counts {
+            bail!("particle count changed in tick {}", tick);
+        }
+
+        if grid == previous.as_slice() {
+            unchanged_ticks += 1;
+            if unchanged_ticks == SETTLED_AFTER_TICKS {
+                return Ok(previous);
+            }
+        } else {
+            unchanged_ticks = 0;
+            previous.copy_from_slice(grid);
+        }
+    }
+
+    bail!("did not come to rest within {} ticks", MAX_SETTLE_TICKS)
+}
+
+/// Steps the backend for WATER_TICKS ticks and returns the grid, in which no particle may be left
+/// directly above a lighter one
+fn run_water(
+    backend: &mut dyn SimulationBackend,
+    width: u32,
+    expected_counts: &[usize; 256],
+) -> anyhow::Result<Vec<u8>> {
+    for tick in 0..WATER_TICKS {
+        backend.step();
+        if material_counts(backend.read_grid()) != *expected_counts {
+            bail!("particle count changed in tick {}", tick);
+        }
+    }
+
+    let grid = backend.read_grid().to_vec();
+    let width = width as usize;
+    if let Some(index) =
+        (width..grid.len()).find(|&index| density(grid[index - width]) > density(grid[index]))
+    {
+        bail!(
+            "a particle is still above a lighter one at ({}, {}) after {} ticks",
+            index % width,
+            index / width - 1,
+            WATER_TICKS
+        );
+    }
+    Ok(grid)
+}
+
+/// Steps the backend through sand sinking into a pool of water, which has to end up below the water.
+/// Water keeps flowing, so the scene has no rest state to compare.
+fn check_sand_sinks_into_water(
+    backend_name: &str,
+    backend: &mut dyn SimulationBackend,
+) -> anyhow::Result<()> {
+    let (width, height) = (32, 24);
+    // Sand on top of a layer of water of the same height, each filling the width of the grid
+    let initial_grid: Vec<u8> = (0..width * height)
+        .map(|index| match index / width {
+            y if y < 10 => 1,
+            y if y < 20 => 3,
+            _ => 0,
+        })
+        .collect();
+    let expected_counts = material_counts(&initial_grid);
+    backend.write_region(0, 0, width, &initial_grid);
+
+    for tick in 0..MAX_SETTLE_TICKS {
+        backend.step();
+        let grid = backend.read_grid();
+        if material_counts(grid) != expected_counts {
+            bail!(
+                "{} changed the particle count in tick {}",
+                backend_name,
+                tick
+            );
+        }
+
+        let sand_at_bottom = grid[((height - 10) * width) as usize..]
+            .iter()
+            .all(|&cell| cell == 1);
+        let water_above = grid[((height - 20) * width) as usize..((height - 10) * width) as usize]
+            .iter()
+            .all(|&cell| cell == 3);
+        if sand_at_bottom && water_above {
+            return Ok(());
+        }
+    }
+
+    bail!(
+        "{} didn't let the sand sink below the water within {} ticks",
+        backend_name,
+        MAX_SETTLE_TICKS
+    )
+}
+
+fn create_gpu_backend(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    width: u32,
+    height: u32,
+) -> Box<dyn SimulationBackend> {
+    // The GPU backend copies its grid into the render textures, which are not drawn here
+    let render_texture = create_particle_grid_texture(device, width, height);
+    let metadata_texture = create_particle_grid_texture(device, width, height);
+    Box::new(GpuBackend::new(
+        device,
+        queue,
+        &render_texture,
+        &metadata_texture,
+        width,
+        height,
+        0,
+    ))
+}
+
+#[test]
+fn cpu_backend_conforms() -> anyhow::Result<()> {
+    check_backend("CPU backend", &mut |width, height| {
+        Box::new(CpuBackend::new(width, height, 0))
+    })
+}
+
+#[test]
+fn gpu_backend_conforms() -> anyhow::Result<()> {
+    let Some((device, queue)) = test_device() else {
+        return Ok(());
+    };
+    check_backend("GPU backend", &mut |width, height| {
+        create_gpu_backend(&device, &queue, width, height)
+    })
+}
+
+#[test]
+fn cpu_backend_conforms_with_water() -> anyhow::Result<()> {
+    check_water_scenes("CPU backend", &mut |width, height| {
+        Box::new(CpuBackend::new(width, height, 0))
+    })
+}
+
+#[test]
+fn gpu_backend_conforms_with_water() -> anyhow::Result<()> {
+    let Some((device, queue)) = test_device() else {
+        return Ok(());
+    };
+    check_water_scenes("GPU backend", &mut |width, height| {
+        create_gpu_backend(&device, &queue, width, height)
+    })
+}
+
+#[test]
+fn cpu_backend_sinks_sand_into_water() -> anyhow::Result<()> {
+    check_sand_sinks_into_water("CPU backend", &mut CpuBackend::new(32, 24, 0))
+}
+
+#[test]
+fn gpu_backend_sinks_sand_into_water() -> anyhow::Result<()> {
+    let Some((device, queue)) = test_device() else {
+        return Ok(());
+    };
+    check_sand_sinks_into_water(
+        "GPU backend",
+        create_gpu_backend(&device, &queue, 32, 24).as_mut(),
+    )
+}
+
+#[test]
+fn gpu_backend_renders_odd_sized_grids() -> anyhow::Result<()> {
+    let Some((device, queue)) = test_device() else {
+        return Ok(());
+    };
+
+    // The packed rows are padded to 256 bytes before they are copied into the render textures
+    for (width, height) in [(1, 30), (37, 29), (63, 45), (257, 3)] {
+        let render_texture = create_particle_grid_texture(&device, width, height);
+        let metadata_texture = create_particle_grid_texture(&device, width, height);
+        let mut backend = GpuBackend::new(
+            &device,
+            &queue,
+            &render_texture,
+            &metadata_texture,
+            width,
+            height,
+            0,
+        );
+        let mut rng = Rng::new(width as u64);
+        let grid: Vec<u8> = (0..width * height)
+            .map(|_| [0, 0, 1, 2, 3][rng.below(5) as usize])
+            .collect();
+        backend.write_region(0, 0, width, &grid);
+        for _ in 0..3 {
+            backend.step();
+        }
+
+        let rendered = read_texture(&device, &queue, &render_texture)?;
+        let rendered_metadata = read_texture(&device, &queue, &metadata_texture)?;
+        if rendered != backend.read_grid() || rendered_metadata != backend.read_metadata() {
+            bail!(
+                "The render textures of the {}x{} grid don't match the simulated grid",
+                width,
+                height
+            );
+        }
+    }
+    Ok(())
+}
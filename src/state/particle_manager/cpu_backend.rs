@@ -0,0 +1,59 @@
+use super::backend::{ParticleGrid, SimulationBackend};
+use super::simulate::simulate_particles;
+
+/// Reference backend running `simulate_particles` on the CPU
+pub struct CpuBackend {
+    grid: ParticleGrid,
+    width: u32,
+    height: u32,
+    // Water flows to the left first on odd ticks
+    tick: u64,
+}
+
+impl CpuBackend {
+    /// `seed` seeds the metadata of placed particles
+    pub fn new(width: u32, height: u32, seed: u64) -> Self {
+        Self {
+            grid: ParticleGrid::new(width, height, seed),
+            width,
+            height,
+            tick: 0,
+        }
+    }
+}
+
+impl SimulationBackend for CpuBackend {
+    fn step(&mut self) {
+        simulate_particles(
+            &mut self.grid.particles,
+            &mut self.grid.metadata,
+            self.height,
+            self.width,
+            self.tick % 2 == 1,
+        );
+        self.tick += 1;
+    }
+
+    fn read_grid(&mut self) -> &[u8] {
+        self.grid.particles.as_slice()
+    }
+
+    fn read_metadata(&mut self) -> &[u8] {
+        self.grid.metadata.as_slice()
+    }
+
+    fn write_region(&mut self, x: u32, y: u32, width: u32, cells: &[u8]) {
+        self.grid.write(x, y, width, cells, None);
+    }
+
+    fn write_region_with_metadata(
+        &mut self,
+        x: u32,
+        y: u32,
+        width: u32,
+        cells: &[u8],
+        metadata: &[u8],
+    ) {
+        self.grid.write(x, y, width, cells, Some(metadata));
+    }
+}
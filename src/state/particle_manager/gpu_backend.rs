@@ -0,0 +1,435 @@
+use super::backend::{ParticleGrid, SimulationBackend};
+use anyhow::bail;
+
+// Workgroup sizes have to match the ones in simulate.wgsl
+const BLOCK_WORKGROUP_SIZE: u32 = 8;
+const PACK_WORKGROUP_SIZE: u32 = 64;
+
+/// Backend stepping the particle grid with a compute shader using the Margolus neighbourhood.
+///
+/// The grid never leaves the GPU during simulation. After every step the cells are packed
+/// into bytes and copied directly into the particle grid and metadata textures of the render pass.
+pub struct GpuBackend {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    width: u32,
+    height: u32,
+
+    cell_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    // Packed materials and metadata with rows padded to the texture copy alignment
+    packed_buffer: wgpu::Buffer,
+    render_texture: wgpu::Texture,
+    metadata_texture: wgpu::Texture,
+    step_pipeline: wgpu::ComputePipeline,
+    pack_pipeline: wgpu::ComputePipeline,
+    params_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    tick: u64,
+
+    // CPU copy of the grid and metadata, only read back from the GPU when it is requested after a step
+    grid: ParticleGrid,
+    particle_grid_is_stale: bool,
+}
+
+impl GpuBackend {
+    /// Creates the backend. `render_texture` and `metadata_texture` are the particle grid and metadata
+    /// textures read by the render pass, `seed` seeds the metadata of placed particles.
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        render_texture: &wgpu::Texture,
+        metadata_texture: &wgpu::Texture,
+        width: u32,
+        height: u32,
+        seed: u64,
+    ) -> Self {
+        let cell_count = width as u64 * height as u64;
+
+        // Each cell is stored as u32 so that blocks can be written without touching their neighbours
+        let cell_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Simulation Cell Buffer"),
+            size: cell_count * 4,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Simulation Readback Buffer"),
+            size: cell_count * 4,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let packed_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Simulation Packed Buffer"),
+            size: packed_row_bytes(width) as u64 * height as u64 * 2,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Simulation Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../../simulate.wgsl").into()),
+        });
+
+        let storage_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Simulation Bind Group Layout"),
+            entries: &[
+                storage_entry(0),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage_entry(2),
+            ],
+        });
+
+        // Params: width, height, block offset, tick and padding, written before every step
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Simulation Params Buffer"),
+            size: 32, // 8 * u32
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        queue.write_buffer(
+            &params_buffer,
+            0,
+            bytemuck::cast_slice(&[width, height, 0, 0, 0, 0, 0, 0]),
+        );
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Simulation Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: cell_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: packed_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Simulation Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            immediate_size: 0,
+        });
+
+        let create_pipeline = |label, entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point: Some(entry_point),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                cache: None,
+            })
+        };
+        let step_pipeline = create_pipeline("Simulation Step Pipeline", "step_blocks");
+        let pack_pipeline = create_pipeline("Simulation Pack Pipeline", "pack");
+
+        let backend = Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            width,
+            height,
+            cell_buffer,
+            readback_buffer,
+            packed_buffer,
+            render_texture: render_texture.clone(),
+            metadata_texture: metadata_texture.clone(),
+            step_pipeline,
+            pack_pipeline,
+            params_buffer,
+            bind_group,
+            tick: 0,
+            grid: ParticleGrid::new(width, height, seed),
+            particle_grid_is_stale: false,
+        };
+
+        // Buffers are zero initialized, which is air, so only the render texture has to be synced
+        backend.pack();
+
+        backend
+    }
+
+    /// Fails if the buffers for a grid of the size are larger than the device allows
+    pub fn check_limits(limits: &wgpu::Limits, width: u32, height: u32) -> anyhow::Result<()> {
+        let cell_bytes = width as u64 * height as u64 * 4;
+        let packed_bytes = packed_row_bytes(width) as u64 * height as u64 * 2;
+        let max_bytes = limits
+            .max_buffer_size
+            .min(limits.max_storage_buffer_binding_size as u64);
+        if cell_bytes.max(packed_bytes) > max_bytes {
+            bail!(
+                "A {}x{} grid needs {} MiB buffers for the GPU backend, this device allows at most {} MiB",
+                width,
+                height,
+                cell_bytes.max(packed_bytes).div_ceil(1024 * 1024),
+                max_bytes / (1024 * 1024)
+            );
+        }
+        Ok(())
+    }
+
+    /// Packs the cells into the render textures
+    fn pack(&self) {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Simulation Pack Encoder"),
+            });
+
+        self.encode_pack(&mut encoder);
+
+        self.queue.submit(std::iter::once(encoder.finish()));
+    }
+
+    /// Updates the CPU copy of the grid and metadata if the GPU stepped since it was last read
+    fn read_back(&mut self) {
+        if !self.particle_grid_is_stale {
+            return;
+        }
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Simulation Readback Encoder"),
+            });
+        encoder.copy_buffer_to_buffer(&self.cell_buffer, 0, &self.readback_buffer, 0, None);
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        self.readback_buffer
+            .map_async(wgpu::MapMode::Read, .., |result| {
+                if let Err(e) = result {
+                    log::error!("Mapping simulation readback buffer failed: {}", e);
+                }
+            });
+        if let Err(e) = self.device.poll(wgpu::PollType::wait_indefinitely()) {
+            log::error!("Waiting for simulation readback failed: {}", e);
+        }
+
+        {
+            let data = self.readback_buffer.get_mapped_range(..);
+            let cells: &[u32] = bytemuck::cast_slice(&data);
+            for ((particle, metadata), cell) in self
+                .grid
+                .particles
+                .iter_mut()
+                .zip(&mut self.grid.metadata)
+                .zip(cells)
+            {
+                *particle = *cell as u8;
+                *metadata = (*cell >> 8) as u8;
+            }
+        }
+        self.readback_buffer.unmap();
+
+        self.particle_grid_is_stale = false;
+    }
+
+    fn encode_pack(&self, encoder: &mut wgpu::CommandEncoder) {
+        let row_bytes = packed_row_bytes(self.width);
+
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Simulation Pack Pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_pipeline(&self.pack_pipeline);
+            compute_pass.set_bind_group(0, &self.bind_group, &[]);
+            // One row of workgroups per packed row, all words in one dimension would exceed the
+            // limit of 65535 workgroups per dimension on large grids
+            compute_pass.dispatch_workgroups(
+                (row_bytes / 4).div_ceil(PACK_WORKGROUP_SIZE),
+                self.height * 2,
+                1,
+            );
+        }
+
+        for (texture, offset) in [
+            (&self.render_texture, 0),
+            (
+                &self.metadata_texture,
+                row_bytes as u64 * self.height as u64,
+            ),
+        ] {
+            encoder.copy_buffer_to_texture(
+                wgpu::TexelCopyBufferInfo {
+                    buffer: &self.packed_buffer,
+                    layout: wgpu::TexelCopyBufferLayout {
+                        offset,
+                        bytes_per_row: Some(row_bytes),
+                        rows_per_image: None,
+                    },
+                },
+                texture.as_image_copy(),
+                texture.size(),
+            );
+        }
+    }
+}
+
+/// Bytes per row of the packed cells, padded to the alignment texture copies require.
+/// The pack entry point in simulate.wgsl uses the same layout.
+fn packed_row_bytes(width: u32) -> u32 {
+    width.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+}
+
+impl SimulationBackend for GpuBackend {
+    fn step(&mut self) {
+        // The vertical block offset alternates every tick so particles can fall one cell per tick,
+        // the horizontal one every second tick so each cell can slide in both directions
+        let offset_x = (self.tick / 2 % 2) as u32;
+        let offset_y = (self.tick % 2) as u32;
+        self.queue.write_buffer(
+            &self.params_buffer,
+            0,
+            bytemuck::cast_slice(&[
+                self.width,
+                self.height,
+                offset_x,
+                offset_y,
+                self.tick as u32,
+                0,
+                0,
+                0,
+            ]),
+        );
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Simulation Step Encoder"),
+            });
+
+        {
+            // One extra block per axis covers the border when the blocks are offset
+            let blocks_x = self.width / 2 + 1;
+            let blocks_y = self.height / 2 + 1;
+
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Simulation Step Pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_pipeline(&self.step_pipeline);
+            compute_pass.set_bind_group(0, &self.bind_group, &[]);
+            compute_pass.dispatch_workgroups(
+                blocks_x.div_ceil(BLOCK_WORKGROUP_SIZE),
+                blocks_y.div_ceil(BLOCK_WORKGROUP_SIZE),
+                1,
+            );
+        }
+
+        self.encode_pack(&mut encoder);
+
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        self.tick += 1;
+        self.particle_grid_is_stale = true;
+    }
+
+    fn read_grid(&mut self) -> &[u8] {
+        self.read_back();
+        self.grid.particles.as_slice()
+    }
+
+    fn read_metadata(&mut self) -> &[u8] {
+        self.read_back();
+        self.grid.metadata.as_slice()
+    }
+
+    fn write_region(&mut self, x: u32, y: u32, width: u32, cells: &[u8]) {
+        self.write_cells(x, y, width, cells, None);
+    }
+
+    fn write_region_with_metadata(
+        &mut self,
+        x: u32,
+        y: u32,
+        width: u32,
+        cells: &[u8],
+        metadata: &[u8],
+    ) {
+        self.write_cells(x, y, width, cells, Some(metadata));
+    }
+
+    fn renders_directly(&self) -> bool {
+        true
+    }
+}
+
+impl GpuBackend {
+    /// Writes the rectangle into the CPU copy of the grid and uploads the changed rows
+    fn write_cells(
+        &mut self,
+        x: u32,
+        y: u32,
+        width: u32,
+        cells: &[u8],
+        new_metadata: Option<&[u8]>,
+    ) {
+        // The current grid tells which cells get new particles
+        self.read_back();
+        self.grid.write(x, y, width, cells, new_metadata);
+
+        for (row, row_cells) in cells.chunks(width as usize).enumerate() {
+            let start = (y as usize + row) * self.width as usize + x as usize;
+            let end = start + row_cells.len();
+            let words: Vec<u32> = self.grid.particles[start..end]
+                .iter()
+                .zip(&self.grid.metadata[start..end])
+                .map(|(&particle, &metadata)| particle as u32 | (metadata as u32) << 8)
+                .collect();
+
+            self.queue.write_buffer(
+                &self.cell_buffer,
+                start as u64 * 4,
+                bytemuck::cast_slice(&words),
+            );
+        }
+
+        self.pack();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packed_rows_are_padded_to_the_copy_alignment() {
+        assert_eq!(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT, 256);
+        assert_eq!(packed_row_bytes(1), 256);
+        assert_eq!(packed_row_bytes(37), 256);
+        assert_eq!(packed_row_bytes(256), 256);
+        assert_eq!(packed_row_bytes(257), 512);
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::VecDeque;
+use std::mem::{size_of, size_of_val};
+
+/// A single cell changed by an edit
+#[derive(Clone, Copy, Debug)]
+pub struct CellChange {
+    /// Index into the particle grid
+    pub index: u32,
+    pub before: u8,
+    pub after: u8,
+    // Metadata of the particles, so undo and redo bring back the same color variation.
+    // Fits into the padding after the materials.
+    pub metadata_before: u8,
+    pub metadata_after: u8,
+}
+
+/// One undoable edit, e.g. a whole brush stroke or a fill
+pub struct Edit {
+    /// Changes in the order they were made
+    pub changes: Vec<CellChange>,
+    /// The whole grid right before the edit, used to rewind the simulation.
+    /// Dropped first when the history runs out of memory. Without the metadata, which would double
+    /// the size of the largest part of the history, so rewound particles get a new color variation.
+    pub world_before: Option<Vec<u8>>,
+}
+
+impl Edit {
+    fn memory_size(&self) -> usize {
+        self.changes.len() * size_of::<CellChange>()
+            + self.world_before.as_ref().map_or(0, Vec::len)
+    }
+}
+
+/// Undo and redo stacks of edits, kept within a memory budget by forgetting the oldest edits
+pub struct History {
+    undo: VecDeque<Edit>,
+    redo: Vec<Edit>,
+    // Whether further changes are added to the newest edit, e.g. while a brush stroke goes on
+    edit_open: bool,
+    memory_budget: usize,
+    memory_used: usize,
+}
+
+impl History {
+    pub fn new(memory_budget: usize) -> Self {
+        Self {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            edit_open: false,
+            memory_budget,
+            memory_used: 0,
+        }
+    }
+
+    /// Adds the changes to the open edit or starts a new one.
+    /// `world_before` is only called when a new edit starts.
+    pub fn record(&mut self, changes: &[CellChange], world_before: impl FnOnce() -> Vec<u8>) {
+        if changes.is_empty() {
+            return;
+        }
+
+        // A new edit makes the undone ones unreachable
+        for edit in self.redo.drain(..) {
+            self.memory_used -= edit.memory_size();
+        }
+
+        match self.undo.back_mut() {
+            Some(edit) if self.edit_open => edit.changes.extend_from_slice(changes),
+            _ => {
+                let world_before = world_before();
+                self.memory_used += world_before.len();
+                self.undo.push_back(Edit {
+                    changes: changes.to_vec(),
+                    world_before: Some(world_before),
+                });
+                self.edit_open = true;
+            }
+        }
+        self.memory_used += size_of_val(changes);
+
+        self.enforce_memory_budget();
+    }
+
+    /// Closes the open edit, the next changes start a new one
+    pub fn finish_edit(&mut self) {
+        self.edit_open = false;
+    }
+
+    /// Forgets all edits, e.g. when the grid they were made on is replaced
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.edit_open = false;
+        self.memory_used = 0;
+    }
+
+    /// Moves the newest edit to the redo stack and returns it
+    pub fn undo(&mut self) -> Option<&Edit> {
+        self.edit_open = false;
+        let edit = self.undo.pop_back()?;
+        self.redo.push(edit);
+        self.redo.last()
+    }
+
+    /// Moves the newest undone edit back to the undo stack and returns it
+    pub fn redo(&mut self) -> Option<&Edit> {
+        self.edit_open = false;
+        let edit = self.redo.pop()?;
+        self.undo.push_back(edit);
+        self.undo.back()
+    }
+
+    fn enforce_memory_budget(&mut self) {
+        // Rewinding is the first thing to go, the cell changes are much smaller than whole grids
+        for edit in self.undo.iter_mut() {
+            if self.memory_used <= self.memory_budget {
+                return;
+            }
+            if let Some(world_before) = edit.world_before.take() {
+                self.memory_used -= world_before.len();
+            }
+        }
+
+        while self.memory_used > self.memory_budget
+            && let Some(edit) = self.undo.pop_front()
+        {
+            self.memory_used -= edit.memory_size();
+        }
+
+        if self.undo.is_empty() {
+            self.edit_open = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(index: u32) -> CellChange {
+        CellChange {
+            index,
+            before: 0,
+            after: 1,
+            metadata_before: 0,
+            metadata_after: 0,
+        }
+    }
+
+    /// Records a finished edit of `cells` changes with a grid of `grid_size` cells before it
+    fn record_edit(history: &mut History, cells: u32, grid_size: usize) {
+        let changes: Vec<_> = (0..cells).map(change).collect();
+        history.record(&changes, || vec![0; grid_size]);
+        history.finish_edit();
+    }
+
+    #[test]
+    fn drops_the_oldest_grids_before_any_edit() {
+        let change_size = size_of::<CellChange>();
+        // Room for the changes of three edits and a single grid
+        let mut history = History::new(3 * 10 * change_size + 100);
+        for _ in 0..3 {
+            record_edit(&mut history, 10, 100);
+        }
+
+        assert_eq!(history.undo.len(), 3);
+        let kept_grids: Vec<_> = history
+            .undo
+            .iter()
+            .map(|edit| edit.world_before.is_some())
+            .collect();
+        assert_eq!(kept_grids, [false, false, true]);
+        assert!(history.memory_used <= history.memory_budget);
+    }
+
+    #[test]
+    fn forgets_the_oldest_edits_when_the_changes_alone_exceed_the_budget() {
+        let change_size = size_of::<CellChange>();
+        let mut history = History::new(2 * 10 * change_size);
+        for cells in [10, 10, 10] {
+            record_edit(&mut history, cells, 100);
+        }
+
+        assert_eq!(history.undo.len(), 2);
+        assert!(history.undo.iter().all(|edit| edit.world_before.is_none()));
+        assert_eq!(history.memory_used, 2 * 10 * change_size);
+    }
+
+    #[test]
+    fn forgets_an_open_edit_that_outgrows_the_budget() {
+        let change_size = size_of::<CellChange>();
+        let mut history = History::new(10 * change_size);
+        history.record(&[change(0); 6], Vec::new);
+        history.record(&[change(1); 6], Vec::new);
+
+        assert!(history.undo.is_empty());
+        assert_eq!(history.memory_used, 0);
+        // The next changes start a new edit instead of extending the forgotten one
+        history.record(&[change(2)], Vec::new);
+        assert_eq!(history.undo.len(), 1);
+        assert_eq!(history.undo[0].changes.len(), 1);
+    }
+
+    #[test]
+    fn new_edits_release_the_memory_of_undone_ones() {
+        let change_size = size_of::<CellChange>();
+        let mut history = History::new(usize::MAX);
+        record_edit(&mut history, 10, 100);
+        record_edit(&mut history, 20, 100);
+        assert!(history.undo().is_some());
+
+        record_edit(&mut history, 5, 100);
+        assert!(history.redo().is_none());
+        assert_eq!(history.memory_used, (10 + 5) * change_size + 2 * 100);
+    }
+
+    #[test]
+    fn zero_budget_keeps_nothing() {
+        let mut history = History::new(0);
+        record_edit(&mut history, 1, 100);
+
+        assert!(history.undo().is_none());
+        assert_eq!(history.memory_used, 0);
+    }
+}
@@ -0,0 +1,71 @@
+use super::theme::Theme;
+
+/// Most materials the palette texture in shader.wgsl has room for, one per possible cell value
+pub const MAX_MATERIALS: usize = 256;
+
+pub struct Material {
+    pub name: &'static str,
+    /// RGB color used for rendering and the palette bar in every theme, in the order of Theme::ALL
+    pub colors: [[f32; 3]; Theme::ALL.len()],
+    /// How much the brightness of single particles differs from the color, 0.1 varies it by up to 10%
+    pub color_variation: f32,
+}
+
+impl Material {
+    pub fn color(&self, theme: Theme) -> [f32; 3] {
+        self.colors[theme as usize]
+    }
+
+    /// Color of a single particle with the metadata in the theme, computed like particle_color in shader.wgsl
+    #[cfg(test)]
+    pub fn particle_color(&self, theme: Theme, metadata: u8) -> [f32; 3] {
+        let variation = (metadata as f32 / 127.5 - 1.0) * self.color_variation;
+        self.color(theme).map(|channel| channel * (1.0 + variation))
+    }
+}
+
+/// Every registered material, a cell's value is its index in here
+pub const MATERIALS: [Material; 4] = [
+    Material {
+        name: "Air",
+        colors: [
+            [1.0, 1.0, 1.0],
+            [0.07, 0.07, 0.09],
+            [0.0, 0.0, 0.0],
+            [1.0, 1.0, 1.0],
+        ],
+        color_variation: 0.0,
+    },
+    Material {
+        name: "Sand",
+        colors: [
+            [0.76, 0.70, 0.50],
+            [0.78, 0.66, 0.42],
+            [1.0, 0.85, 0.0],
+            [0.90, 0.62, 0.0],
+        ],
+        color_variation: 0.08,
+    },
+    Material {
+        name: "Stone",
+        colors: [
+            [0.57, 0.56, 0.52],
+            [0.42, 0.42, 0.45],
+            [0.0, 0.6, 1.0],
+            [0.0, 0.45, 0.70],
+        ],
+        color_variation: 0.05,
+    },
+    Material {
+        name: "Water",
+        colors: [
+            [0.25, 0.45, 0.85],
+            [0.20, 0.38, 0.72],
+            [1.0, 1.0, 1.0],
+            [0.34, 0.71, 0.91],
+        ],
+        color_variation: 0.03,
+    },
+];
+
+const _: () = assert!(MATERIALS.len() <= MAX_MATERIALS);
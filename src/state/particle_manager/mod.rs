@@ -1,93 +1,744 @@
-use crate::RADIUS_ADD_PARTICLES;
-use simulate::simulate_particles;
-use winit::dpi::PhysicalSize;
+use brush::{BrushShape, PlacementMode};
+use history::{CellChange, History};
+use motion::MotionTracker;
+use random::Rng;
+use stamp::Stamp;
+use std::path::{Path, PathBuf};
 
+pub use backend::{BackendKind, SimulationBackend};
+pub use brush::Brush;
+pub use cpu_backend::CpuBackend;
+pub use gpu_backend::GpuBackend;
+pub use material::{MATERIALS, MAX_MATERIALS};
+pub use motion::chunk_count;
+pub use scene::Scene;
+pub use shape::{MAX_SHAPE_POINTS, Shape, ShapeKind};
+pub use theme::Theme;
+
+mod backend;
+mod brush;
+#[cfg(test)]
+mod conformance;
+mod cpu_backend;
+mod gpu_backend;
+mod history;
+mod material;
+mod motion;
+mod random;
+mod scene;
+mod shape;
 mod simulate;
+mod stamp;
+mod theme;
 
 pub struct ParticleManager {
-    particle_grid: Vec<u8>,
+    backend: Box<dyn SimulationBackend + Send>,
     width: u32,
     height: u32,
 
     // the currently selected material to be created when clicking
     selected_material: u8,
+    // shape and placement mode used when painting
+    brush: Brush,
+    rng: Rng,
+    // user edits that can be undone
+    history: History,
+    // cells copied from a selection or loaded from a stamp file, pasted at the cursor
+    clipboard: Option<Stamp>,
+    // what happened in the last tick, only tracked while a debug overlay shows it
+    motion: Option<MotionTracker>,
 }
 
 impl ParticleManager {
-    pub fn new(width: u32, height: u32) -> Self {
-        let particle_grid = vec![0; width as usize * height as usize];
-
+    /// `seed` seeds the random choices of the brush, like where spraying places particles
+    pub fn new(
+        backend: Box<dyn SimulationBackend + Send>,
+        width: u32,
+        height: u32,
+        seed: u64,
+        undo_memory_budget: usize,
+    ) -> Self {
         Self {
-            particle_grid,
+            backend,
             width,
             height,
 
             // Initialize to sand
             selected_material: 1,
+            brush: Brush::default(),
+            rng: Rng::new(seed),
+            history: History::new(undo_memory_budget),
+            clipboard: None,
+            motion: None,
         }
     }
 
     pub fn cycle_material_up(&mut self) {
-        // Cycle through materials: 0 (air) -> 1 (sand) -> 2 (stone) -> 0
-        self.selected_material = (self.selected_material + 1) % 3;
+        // Cycle through the registered materials, from the last one back to air
+        self.selected_material = (self.selected_material + 1) % MATERIALS.len() as u8;
     }
 
     pub fn cycle_material_down(&mut self) {
-        // Cycle backwards: 0 (air) -> 2 (stone) -> 1 (sand) -> 0
+        // Cycle backwards, from air to the last registered material
         self.selected_material = if self.selected_material == 0 {
-            2
+            MATERIALS.len() as u8 - 1
         } else {
             self.selected_material - 1
         };
     }
 
-    pub fn add_material_at_cursor(
-        &mut self,
-        window_size: &PhysicalSize<u32>,
-        cursor_x: f64,
-        cursor_y: f64,
-    ) {
-        // Convert cursor position to grid coordinates
-        let grid_x = ((cursor_x / window_size.width as f64) * self.width as f64) as i32;
-        let grid_y = ((cursor_y / window_size.height as f64) * self.height as f64) as i32;
-
-        let radius = RADIUS_ADD_PARTICLES as i32;
-
-        // Add the selected material in a circle around the cursor
-        for dy in -radius..=radius {
-            for dx in -radius..=radius {
-                // Check if point is within circle
-                if dx * dx + dy * dy <= radius * radius {
-                    let x = grid_x + dx;
-                    let y = grid_y + dy;
-
-                    // Check bounds
-                    if x >= 0 && x < self.width as i32 && y >= 0 && y < self.height as i32 {
-                        let index = (y * self.width as i32 + x) as usize;
-                        self.particle_grid[index] = self.selected_material;
+    /// Selects the material, unless it isn't registered
+    pub fn select_material(&mut self, material: u8) {
+        if (material as usize) < MATERIALS.len() {
+            self.selected_material = material;
+        }
+    }
+
+    pub fn brush_mut(&mut self) -> &mut Brush {
+        &mut self.brush
+    }
+
+    /// Paints the selected material along the line from `from` to `to`
+    pub fn add_material_line(&mut self, from: (i32, i32), to: (i32, i32)) {
+        self.paint_line(from, to, self.selected_material, self.brush);
+    }
+
+    /// Paints air along the line from `from` to `to`, keeping the selected material.
+    /// Only placing into air would never erase anything, so the eraser overwrites in that mode.
+    pub fn erase_line(&mut self, from: (i32, i32), to: (i32, i32)) {
+        let mut brush = self.brush;
+        if brush.mode == PlacementMode::OnlyIntoAir {
+            brush.mode = PlacementMode::Overwrite;
+        }
+        self.paint_line(from, to, 0, brush);
+    }
+
+    /// Selects the material at the grid position
+    pub fn pick_material(&mut self, x: i32, y: i32) {
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            return;
+        }
+
+        let index = y as usize * self.width as usize + x as usize;
+        let material = self.backend.read_grid()[index];
+        self.select_material(material);
+    }
+
+    /// Draws the shape with the selected material in a single edit.
+    /// Lines and outlines are as thick as the brush radius.
+    pub fn draw_shape(&mut self, shape: &Shape) {
+        let brush = self.brush;
+        let material = self.selected_material;
+        let (x0, y0, x1, y1) = shape.bounds(brush.radius);
+
+        self.history.finish_edit();
+        self.edit_region(x0, y0, x1, y1, |x, y, cell| {
+            if shape.contains(x, y, brush.radius) && brush.places_into(*cell) {
+                *cell = material;
+            }
+        });
+        self.history.finish_edit();
+    }
+
+    /// Replaces the 4-connected region of equal material at the grid position with the selected material.
+    ///
+    /// Uses a scanline fill with an explicit stack, so even regions spanning the whole grid
+    /// need neither deep recursion nor a stack entry per cell.
+    pub fn fill(&mut self, x: i32, y: i32) {
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            return;
+        }
+
+        let width = self.width as usize;
+        let height = self.height as usize;
+        let material = self.selected_material;
+
+        let mut grid = self.backend.read_grid().to_vec();
+        let target = grid[y as usize * width + x as usize];
+        if target == material {
+            return;
+        }
+
+        // Bounding box of the filled cells, only this part is written back
+        let (mut min_x, mut min_y) = (x as usize, y as usize);
+        let (mut max_x, mut max_y) = (x as usize, y as usize);
+
+        let mut stack = vec![(x as usize, y as usize)];
+        while let Some((x, y)) = stack.pop() {
+            let row = y * width;
+            if grid[row + x] != target {
+                continue;
+            }
+
+            // Extend the span to the left and right and fill it
+            let mut left = x;
+            while left > 0 && grid[row + left - 1] == target {
+                left -= 1;
+            }
+            let mut right = x;
+            while right + 1 < width && grid[row + right + 1] == target {
+                right += 1;
+            }
+            grid[row + left..=row + right].fill(material);
+
+            min_x = min_x.min(left);
+            max_x = max_x.max(right);
+            min_y = min_y.min(y);
+            max_y = max_y.max(y);
+
+            // Queue one seed for every run of target cells above and below the span
+            for neighbour_y in [y.wrapping_sub(1), y + 1] {
+                if neighbour_y >= height {
+                    continue;
+                }
+
+                let neighbour_row = neighbour_y * width;
+                let mut in_run = false;
+                for neighbour_x in left..=right {
+                    if grid[neighbour_row + neighbour_x] == target {
+                        if !in_run {
+                            stack.push((neighbour_x, neighbour_y));
+                            in_run = true;
+                        }
+                    } else {
+                        in_run = false;
                     }
                 }
             }
         }
+
+        let region_width = max_x - min_x + 1;
+        let mut region = Vec::with_capacity(region_width * (max_y - min_y + 1));
+        for y in min_y..=max_y {
+            let start = y * width + min_x;
+            region.extend_from_slice(&grid[start..start + region_width]);
+        }
+
+        self.history.finish_edit();
+        self.write_region(min_x as u32, min_y as u32, region_width as u32, &region);
+        self.history.finish_edit();
+    }
+
+    // --- Clipboard ---
+    /// Copies the rectangle from (x0, y0) to (x1, y1) (inclusive, clipped to the grid) to the clipboard
+    pub fn copy(&mut self, (x0, y0): (i32, i32), (x1, y1): (i32, i32)) {
+        let (x0, x1) = (x0.min(x1).max(0), x0.max(x1).min(self.width as i32 - 1));
+        let (y0, y1) = (y0.min(y1).max(0), y0.max(y1).min(self.height as i32 - 1));
+        if x0 > x1 || y0 > y1 {
+            return;
+        }
+
+        self.clipboard = Some(Stamp {
+            width: (x1 - x0 + 1) as u32,
+            height: (y1 - y0 + 1) as u32,
+            cells: self.read_region(x0, y0, x1, y1),
+        });
+    }
+
+    /// Copies the rectangle to the clipboard and fills it with air
+    pub fn cut(&mut self, from: (i32, i32), to: (i32, i32)) {
+        self.copy(from, to);
+
+        self.history.finish_edit();
+        self.edit_region(
+            from.0.min(to.0),
+            from.1.min(to.1),
+            from.0.max(to.0),
+            from.1.max(to.1),
+            |_, _, cell| {
+                *cell = 0;
+            },
+        );
+        self.history.finish_edit();
+    }
+
+    /// Pastes the clipboard centered at the grid position, into the cells allowed by the placement mode
+    pub fn paste(&mut self, (x, y): (i32, i32)) {
+        let Some(stamp) = self.clipboard.take() else {
+            return;
+        };
+        let brush = self.brush;
+        let x0 = x - stamp.width as i32 / 2;
+        let y0 = y - stamp.height as i32 / 2;
+
+        self.history.finish_edit();
+        self.edit_region(
+            x0,
+            y0,
+            x0 + stamp.width as i32 - 1,
+            y0 + stamp.height as i32 - 1,
+            |x, y, cell| {
+                let material =
+                    stamp.cells[(y - y0) as usize * stamp.width as usize + (x - x0) as usize];
+                if brush.places_into(*cell) {
+                    *cell = material;
+                }
+            },
+        );
+        self.history.finish_edit();
+
+        self.clipboard = Some(stamp);
+    }
+
+    pub fn rotate_clipboard(&mut self) {
+        if let Some(stamp) = &mut self.clipboard {
+            stamp.rotate_clockwise();
+        }
+    }
+
+    pub fn flip_clipboard_horizontally(&mut self) {
+        if let Some(stamp) = &mut self.clipboard {
+            stamp.flip_horizontally();
+        }
+    }
+
+    pub fn flip_clipboard_vertically(&mut self) {
+        if let Some(stamp) = &mut self.clipboard {
+            stamp.flip_vertically();
+        }
+    }
+
+    /// Saves the rectangle as a new stamp file in the directory and returns its path,
+    /// it is copied to the clipboard as well
+    pub fn save_stamp(
+        &mut self,
+        from: (i32, i32),
+        to: (i32, i32),
+        directory: &Path,
+    ) -> anyhow::Result<PathBuf> {
+        self.copy(from, to);
+        match &self.clipboard {
+            Some(stamp) => stamp.save_new(directory),
+            None => anyhow::bail!("The selection is outside of the grid"),
+        }
+    }
+
+    /// Loads a stamp file into the clipboard
+    pub fn load_stamp(&mut self, path: &Path) -> anyhow::Result<()> {
+        self.clipboard = Some(Stamp::load(path)?);
+        Ok(())
+    }
+
+    /// Width and height of the clipboard, (0, 0) if it is empty
+    pub fn clipboard_size(&self) -> (u32, u32) {
+        self.clipboard
+            .as_ref()
+            .map_or((0, 0), |stamp| (stamp.width, stamp.height))
+    }
+
+    /// Paints `material` with the brush swept along the line from `from` to `to`,
+    /// so fast mouse movements still leave a continuous stroke.
+    /// Lines are added to the same undoable edit until `finish_edit` is called.
+    fn paint_line(&mut self, from: (i32, i32), to: (i32, i32), material: u8, brush: Brush) {
+        let radius = brush.radius as i32;
+
+        let x0 = from.0.min(to.0) - radius;
+        let y0 = from.1.min(to.1) - radius;
+        let x1 = from.0.max(to.0) + radius;
+        let y1 = from.1.max(to.1) + radius;
+        let region_width = (x1 - x0 + 1) as usize;
+
+        // The brush moves along the line one cell at a time. It covers one run of cells per row
+        // and the runs of consecutive steps touch, so the stroke covers one run per row as well.
+        let mut row_runs: Vec<Option<(i32, i32)>> = vec![None; (y1 - y0 + 1) as usize];
+        let steps = (to.0 - from.0).abs().max((to.1 - from.1).abs());
+        for step in 0..=steps {
+            let t = if steps > 0 {
+                step as f32 / steps as f32
+            } else {
+                0.0
+            };
+            let center_x = from.0 + ((to.0 - from.0) as f32 * t).round() as i32;
+            let center_y = from.1 + ((to.1 - from.1) as f32 * t).round() as i32;
+
+            for dy in -radius..=radius {
+                if let Some(reach) = brush.row_reach(dy) {
+                    let run = &mut row_runs[(center_y + dy - y0) as usize];
+                    *run = Some(match *run {
+                        Some((start, end)) => {
+                            (start.min(center_x - reach), end.max(center_x + reach))
+                        }
+                        None => (center_x - reach, center_x + reach),
+                    });
+                }
+            }
+        }
+
+        let mut covered = vec![false; region_width * row_runs.len()];
+        for (row, run) in row_runs.iter().enumerate() {
+            if let Some((start, end)) = *run {
+                let row_start = row * region_width;
+                covered[row_start + (start - x0) as usize..=row_start + (end - x0) as usize]
+                    .fill(true);
+            }
+        }
+
+        // The spray only paints a random part of the covered cells
+        if brush.shape == BrushShape::Spray {
+            for cell in covered.iter_mut().filter(|cell| **cell) {
+                *cell = self.rng.chance(brush.spray_density);
+            }
+        }
+
+        self.edit_region(x0, y0, x1, y1, |x, y, cell| {
+            let index = (y - y0) as usize * region_width + (x - x0) as usize;
+            if covered[index] && brush.places_into(*cell) {
+                *cell = material;
+            }
+        });
+    }
+
+    /// Calls `edit` for every cell of the rectangle from (x0, y0) to (x1, y1) (inclusive, clipped to the grid)
+    /// and writes the result back to the simulation backend
+    fn edit_region(
+        &mut self,
+        x0: i32,
+        y0: i32,
+        x1: i32,
+        y1: i32,
+        mut edit: impl FnMut(i32, i32, &mut u8),
+    ) {
+        // Clip to the grid
+        let x0 = x0.max(0);
+        let y0 = y0.max(0);
+        let x1 = x1.min(self.width as i32 - 1);
+        let y1 = y1.min(self.height as i32 - 1);
+        if x0 > x1 || y0 > y1 {
+            return;
+        }
+
+        let region_width = (x1 - x0 + 1) as usize;
+        let mut region = self.read_region(x0, y0, x1, y1);
+
+        for (i, cell) in region.iter_mut().enumerate() {
+            let x = x0 + (i % region_width) as i32;
+            let y = y0 + (i / region_width) as i32;
+            edit(x, y, cell);
+        }
+
+        self.write_region(x0 as u32, y0 as u32, region_width as u32, &region);
+    }
+
+    /// Copies the rectangle from (x0, y0) to (x1, y1) (inclusive, inside the grid) out of the grid
+    fn read_region(&mut self, x0: i32, y0: i32, x1: i32, y1: i32) -> Vec<u8> {
+        let region_width = (x1 - x0 + 1) as usize;
+        let width = self.width as usize;
+        let grid = self.backend.read_grid();
+
+        let mut region = Vec::with_capacity(region_width * (y1 - y0 + 1) as usize);
+        for y in y0..=y1 {
+            let start = y as usize * width + x0 as usize;
+            region.extend_from_slice(&grid[start..start + region_width]);
+        }
+        region
+    }
+
+    /// Writes the region to the simulation backend and records the changed cells in the history
+    fn write_region(&mut self, x: u32, y: u32, region_width: u32, region: &[u8]) {
+        let width = self.width as usize;
+        let grid = self.backend.read_grid();
+
+        let mut changes = Vec::new();
+        for (i, &after) in region.iter().enumerate() {
+            let index = (y as usize + i / region_width as usize) * width
+                + x as usize
+                + i % region_width as usize;
+            let before = grid[index];
+            if before != after {
+                changes.push(CellChange {
+                    index: index as u32,
+                    before,
+                    after,
+                    metadata_before: 0,
+                    metadata_after: 0,
+                });
+            }
+        }
+        let metadata = self.backend.read_metadata();
+        for change in &mut changes {
+            change.metadata_before = metadata[change.index as usize];
+        }
+
+        // The backend picks the metadata of the new particles
+        self.backend.write_region(x, y, region_width, region);
+        let metadata = self.backend.read_metadata();
+        for change in &mut changes {
+            change.metadata_after = metadata[change.index as usize];
+        }
+
+        let backend = &mut self.backend;
+        self.history.record(&changes, || {
+            let mut world_before = backend.read_grid().to_vec();
+            for change in &changes {
+                world_before[change.index as usize] = change.before;
+            }
+            world_before
+        });
+    }
+
+    /// Ends the current brush stroke, the next one is undone separately
+    pub fn finish_edit(&mut self) {
+        self.history.finish_edit();
+    }
+
+    /// Reverts the newest edit.
+    ///
+    /// Particles keep moving after an edit, so only cells that still hold what the edit placed are restored
+    /// and everything else that happened since is kept.
+    pub fn undo(&mut self) {
+        if let Some(edit) = self.history.undo() {
+            let changes = edit.changes.iter().rev().map(|change| {
+                (
+                    change.index,
+                    change.after,
+                    change.before,
+                    change.metadata_before,
+                )
+            });
+            apply_changes(self.backend.as_mut(), self.width, changes);
+        }
+    }
+
+    /// Repeats the newest undone edit, again only where cells still hold what undo restored
+    pub fn redo(&mut self) {
+        if let Some(edit) = self.history.redo() {
+            let changes = edit.changes.iter().map(|change| {
+                (
+                    change.index,
+                    change.before,
+                    change.after,
+                    change.metadata_after,
+                )
+            });
+            apply_changes(self.backend.as_mut(), self.width, changes);
+        }
+    }
+
+    /// Reverts the newest edit by restoring the whole grid to how it was right before it,
+    /// which also rewinds everything the simulation did since.
+    /// Falls back to `undo` if the grid was dropped to stay within the memory budget.
+    pub fn rewind(&mut self) {
+        let Some(edit) = self.history.undo() else {
+            return;
+        };
+
+        match &edit.world_before {
+            Some(world_before) => self.backend.write_region(0, 0, self.width, world_before),
+            None => {
+                let changes = edit.changes.iter().rev().map(|change| {
+                    (
+                        change.index,
+                        change.after,
+                        change.before,
+                        change.metadata_before,
+                    )
+                });
+                apply_changes(self.backend.as_mut(), self.width, changes);
+            }
+        }
+    }
+
+    /// Switches to a backend of a different size, keeping the content anchored to the bottom left corner.
+    /// Edits can't be undone afterwards.
+    pub fn resize(
+        &mut self,
+        mut backend: Box<dyn SimulationBackend + Send>,
+        width: u32,
+        height: u32,
+    ) {
+        let (old_width, old_height) = (self.width as usize, self.height as usize);
+        let (new_width, new_height) = (width as usize, height as usize);
+        let old_metadata = self.backend.read_metadata().to_vec();
+        let old_grid = self.backend.read_grid();
+
+        // Particles keep their metadata
+        let mut grid = vec![0; new_width * new_height];
+        let mut metadata = vec![0; new_width * new_height];
+        let columns = old_width.min(new_width);
+        for row in 1..=old_height.min(new_height) {
+            let old_start = (old_height - row) * old_width;
+            let new_start = (new_height - row) * new_width;
+            grid[new_start..new_start + columns]
+                .copy_from_slice(&old_grid[old_start..old_start + columns]);
+            metadata[new_start..new_start + columns]
+                .copy_from_slice(&old_metadata[old_start..old_start + columns]);
+        }
+        backend.write_region_with_metadata(0, 0, width, &grid, &metadata);
+
+        self.backend = backend;
+        self.width = width;
+        self.height = height;
+        self.history.clear();
+        if self.motion.is_some() {
+            self.motion = Some(MotionTracker::new(width, height));
+        }
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
     }
 
     pub fn simulate_particles(&mut self) {
-        simulate_particles(&mut self.particle_grid, self.height, self.width);
+        match &mut self.motion {
+            Some(motion) => {
+                motion.record_before(self.backend.as_mut());
+                self.backend.step();
+                motion.record_after(self.backend.as_mut());
+            }
+            None => self.backend.step(),
+        }
+    }
+
+    /// Starts or stops tracking which cells move, which reads the grid back from the backend every tick
+    pub fn set_motion_tracking(&mut self, enabled: bool) {
+        if enabled != self.motion.is_some() {
+            self.motion = enabled.then(|| MotionTracker::new(self.width, self.height));
+        }
+    }
+
+    pub fn motion(&self) -> Option<&MotionTracker> {
+        self.motion.as_ref()
+    }
+
+    pub fn particle_grid(&mut self) -> &[u8] {
+        self.backend.read_grid()
+    }
+
+    pub fn particle_metadata(&mut self) -> &[u8] {
+        self.backend.read_metadata()
     }
 
-    pub fn particle_grid(&self) -> &[u8] {
-        self.particle_grid.as_slice()
+    /// Whether the simulation backend writes the particle grid textures of the render pass itself
+    pub fn renders_directly(&self) -> bool {
+        self.backend.renders_directly()
+    }
+
+    /// Number of cells of each registered material
+    pub fn particle_counts(&mut self) -> Vec<u32> {
+        let mut counts = vec![0; MATERIALS.len()];
+        for &cell in self.backend.read_grid() {
+            if let Some(count) = counts.get_mut(cell as usize) {
+                *count += 1;
+            }
+        }
+        counts
     }
 
     pub fn selected_material(&self) -> u8 {
         self.selected_material
     }
 
-    pub fn width(&self) -> u32 {
-        self.width
+    pub fn brush(&self) -> Brush {
+        self.brush
+    }
+}
+
+/// Sets every cell (index, expected, new, new metadata) that still contains `expected` to `new`
+/// with the given metadata, writing only the bounding box of the changed cells back to the backend
+fn apply_changes(
+    backend: &mut dyn SimulationBackend,
+    width: u32,
+    changes: impl Iterator<Item = (u32, u8, u8, u8)> + Clone,
+) {
+    let width = width as usize;
+    let (mut min_x, mut min_y) = (usize::MAX, usize::MAX);
+    let (mut max_x, mut max_y) = (0, 0);
+    for (index, _, _, _) in changes.clone() {
+        let (x, y) = (index as usize % width, index as usize / width);
+        min_x = min_x.min(x);
+        min_y = min_y.min(y);
+        max_x = max_x.max(x);
+        max_y = max_y.max(y);
     }
+    if min_x > max_x {
+        return;
+    }
+
+    let region_width = max_x - min_x + 1;
+    let cell_count = region_width * (max_y - min_y + 1);
+    let mut metadata_region = Vec::with_capacity(cell_count);
+    let metadata = backend.read_metadata();
+    for y in min_y..=max_y {
+        let start = y * width + min_x;
+        metadata_region.extend_from_slice(&metadata[start..start + region_width]);
+    }
+    let mut region = Vec::with_capacity(cell_count);
+    let grid = backend.read_grid();
+    for y in min_y..=max_y {
+        let start = y * width + min_x;
+        region.extend_from_slice(&grid[start..start + region_width]);
+    }
+
+    for (index, expected, new, new_metadata) in changes {
+        let (x, y) = (index as usize % width, index as usize / width);
+        let region_index = (y - min_y) * region_width + x - min_x;
+        if region[region_index] == expected {
+            region[region_index] = new;
+            metadata_region[region_index] = new_metadata;
+        }
+    }
+
+    backend.write_region_with_metadata(
+        min_x as u32,
+        min_y as u32,
+        region_width as u32,
+        &region,
+        &metadata_region,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn particle_manager(seed: u64) -> ParticleManager {
+        let backend = Box::new(CpuBackend::new(32, 32, seed));
+        ParticleManager::new(backend, 32, 32, seed, usize::MAX)
+    }
+
+    #[test]
+    fn placed_particles_follow_the_seed() {
+        let mut metadata = Vec::new();
+        for seed in [1, 1, 2] {
+            let mut particle_manager = particle_manager(seed);
+            particle_manager.add_material_line((16, 16), (16, 16));
+            metadata.push(particle_manager.particle_metadata().to_vec());
+        }
+
+        assert_eq!(metadata[0], metadata[1]);
+        assert_ne!(metadata[0], metadata[2]);
+    }
+
+    #[test]
+    fn undo_and_redo_restore_the_metadata() {
+        let mut particle_manager = particle_manager(3);
+        particle_manager.select_material(2);
+        particle_manager.add_material_line((10, 10), (20, 10));
+        particle_manager.finish_edit();
+        let placed = particle_manager.particle_metadata().to_vec();
+
+        particle_manager.erase_line((0, 10), (31, 10));
+        particle_manager.finish_edit();
+        particle_manager.undo();
+        assert_eq!(particle_manager.particle_metadata(), placed.as_slice());
+
+        particle_manager.undo();
+        particle_manager.redo();
+        assert_eq!(particle_manager.particle_metadata(), placed.as_slice());
+    }
+
+    #[test]
+    fn erasing_ignores_only_into_air() {
+        let mut particle_manager = particle_manager(4);
+        particle_manager.add_material_line((10, 10), (20, 10));
+        particle_manager.brush_mut().mode = PlacementMode::OnlyIntoAir;
+        particle_manager.erase_line((10, 10), (20, 10));
 
-    pub fn height(&self) -> u32 {
-        self.height
+        assert!(
+            particle_manager
+                .particle_grid()
+                .iter()
+                .all(|&cell| cell == 0)
+        );
     }
 }
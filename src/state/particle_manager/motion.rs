@@ -0,0 +1,141 @@
+use super::backend::SimulationBackend;
+use crate::DEBUG_CHUNK_SIZE;
+
+// Directions a particle moved in during the last tick, see MotionTracker::motion
+pub const MOVED_DOWN: u8 = 1;
+pub const MOVED_DOWN_LEFT: u8 = 2;
+pub const MOVED_DOWN_RIGHT: u8 = 3;
+pub const MOVED_LEFT: u8 = 4;
+pub const MOVED_RIGHT: u8 = 5;
+pub const MOVED_UP: u8 = 6;
+pub const MOVED_UP_LEFT: u8 = 7;
+pub const MOVED_UP_RIGHT: u8 = 8;
+
+// Where a particle that moved in each direction came from, relative to the cell it moved into.
+// Falls come first since they are the most common moves.
+const SOURCES: [(isize, isize, u8); 8] = [
+    (0, -1, MOVED_DOWN),
+    (1, -1, MOVED_DOWN_LEFT),
+    (-1, -1, MOVED_DOWN_RIGHT),
+    (1, 0, MOVED_LEFT),
+    (-1, 0, MOVED_RIGHT),
+    (0, 1, MOVED_UP),
+    (1, 1, MOVED_UP_LEFT),
+    (-1, 1, MOVED_UP_RIGHT),
+];
+
+/// Finds out what happened in a tick by comparing the grid before and after it, for the debug overlays.
+///
+/// Backends don't report which particles they moved, but a particle's metadata moves along with it.
+/// A cell that now holds the material and metadata one of its eight neighbours held before the tick
+/// is taken to be that particle, e.g. water flowing sideways or pushed up by sinking sand. Particles
+/// with equal metadata can be mixed up, which is good enough to see what is going on.
+pub struct MotionTracker {
+    width: usize,
+    height: usize,
+    previous_grid: Vec<u8>,
+    previous_metadata: Vec<u8>,
+    motion: Vec<u8>,
+    chunk_activity: Vec<u8>,
+}
+
+impl MotionTracker {
+    pub fn new(width: u32, height: u32) -> Self {
+        let cell_count = width as usize * height as usize;
+        let (chunks_x, chunks_y) = chunk_count(width, height);
+        Self {
+            width: width as usize,
+            height: height as usize,
+            previous_grid: vec![0; cell_count],
+            previous_metadata: vec![0; cell_count],
+            motion: vec![0; cell_count],
+            chunk_activity: vec![0; chunks_x as usize * chunks_y as usize],
+        }
+    }
+
+    /// Remembers the grid before the backend steps
+    pub fn record_before(&mut self, backend: &mut dyn SimulationBackend) {
+        self.previous_grid.copy_from_slice(backend.read_grid());
+        self.previous_metadata
+            .copy_from_slice(backend.read_metadata());
+    }
+
+    /// Compares the grid after the backend stepped with the one before
+    pub fn record_after(&mut self, backend: &mut dyn SimulationBackend) {
+        let (chunks_x, _) = chunk_count(self.width as u32, self.height as u32);
+        let (chunks_x, chunk_size) = (chunks_x as usize, DEBUG_CHUNK_SIZE as usize);
+        self.chunk_activity.fill(0);
+
+        // Only the debug overlays track motion, so copying the grid is fine
+        let grid = backend.read_grid().to_vec();
+        for (index, &cell) in grid.iter().enumerate() {
+            let (x, y) = (index % self.width, index / self.width);
+            if cell != self.previous_grid[index] {
+                self.chunk_activity[y / chunk_size * chunks_x + x / chunk_size] = 1;
+            }
+        }
+
+        let metadata = backend.read_metadata();
+        for (index, motion) in self.motion.iter_mut().enumerate() {
+            *motion = 0;
+            let (x, y) = (index % self.width, index / self.width);
+            if grid[index] == self.previous_grid[index] || grid[index] == 0 {
+                continue;
+            }
+
+            *motion = SOURCES
+                .iter()
+                .find_map(|&(dx, dy, direction)| {
+                    let source_x = x.checked_add_signed(dx).filter(|&x| x < self.width)?;
+                    let source_y = y.checked_add_signed(dy).filter(|&y| y < self.height)?;
+                    let source = source_y * self.width + source_x;
+                    (self.previous_grid[source] == grid[index]
+                        && self.previous_metadata[source] == metadata[index])
+                        .then_some(direction)
+                })
+                .unwrap_or(0);
+        }
+    }
+
+    /// Direction every cell's particle moved in during the last tick, 0 if it didn't move
+    pub fn motion(&self) -> &[u8] {
+        &self.motion
+    }
+
+    /// 1 for every chunk of DEBUG_CHUNK_SIZE cells that changed in the last tick, 0 for unchanged ones
+    pub fn chunk_activity(&self) -> &[u8] {
+        &self.chunk_activity
+    }
+}
+
+/// Number of chunks the grid is split into horizontally and vertically
+pub fn chunk_count(width: u32, height: u32) -> (u32, u32) {
+    (
+        width.div_ceil(DEBUG_CHUNK_SIZE),
+        height.div_ceil(DEBUG_CHUNK_SIZE),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::cpu_backend::CpuBackend;
+    use super::*;
+
+    #[test]
+    fn sideways_and_upward_moves_are_found() {
+        let mut backend = CpuBackend::new(3, 2, 0);
+        let mut tracker = MotionTracker::new(3, 2);
+        // Sand above two water particles, told apart by their metadata
+        backend.write_region_with_metadata(0, 0, 3, &[1, 0, 0, 3, 3, 0], &[10, 0, 0, 20, 30, 0]);
+        tracker.record_before(&mut backend);
+
+        // The sand sinks into the water below it and the other water flows right
+        backend.write_region_with_metadata(0, 0, 3, &[3, 0, 0, 1, 0, 3], &[20, 0, 0, 10, 0, 30]);
+        tracker.record_after(&mut backend);
+
+        assert_eq!(
+            tracker.motion(),
+            [MOVED_UP, 0, 0, MOVED_DOWN, 0, MOVED_RIGHT]
+        );
+    }
+}
@@ -0,0 +1,31 @@
+/// Small seedable xorshift generator, good enough for scenes and effects but not for anything else
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // xorshift gets stuck on zero, so mix the seed and make sure a bit is set
+        Self {
+            state: seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1,
+        }
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        (self.state >> 32) as u32
+    }
+
+    /// Returns a value in 0..bound
+    pub fn below(&mut self, bound: u32) -> u32 {
+        ((self.next_u32() as u64 * bound as u64) >> 32) as u32
+    }
+
+    /// Returns true with the given probability (0.0 to 1.0)
+    pub fn chance(&mut self, probability: f32) -> bool {
+        // Scaled by 2^32 rather than u32::MAX, so a probability of 1.0 is always true
+        (self.next_u32() as f64) < probability as f64 * 4_294_967_296.0
+    }
+}
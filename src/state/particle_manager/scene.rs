@@ -0,0 +1,103 @@
+use super::random::Rng;
+use super::stamp::Stamp;
+use anyhow::bail;
+use std::path::Path;
+
+/// Names of the generated scenes, in the order they are listed in errors
+const SCENE_NAMES: [&str; 4] = ["empty", "sand-pile", "noise", "ledges"];
+
+/// What the grid is filled with on startup
+pub enum Scene {
+    Empty,
+    /// A block of sand falling down in the middle
+    SandPile,
+    /// Air, sand and stone scattered at random
+    Noise,
+    /// Rows of stone ledges with sand raining down on them
+    Ledges,
+    /// A stamp file placed at the bottom in the middle
+    Stamp(Stamp),
+}
+
+impl Scene {
+    /// Parses a scene name, anything else is loaded as a stamp file
+    pub fn parse(name: &str) -> anyhow::Result<Self> {
+        Ok(match name {
+            "empty" => Scene::Empty,
+            "sand-pile" => Scene::SandPile,
+            "noise" => Scene::Noise,
+            "ledges" => Scene::Ledges,
+            _ if Path::new(name).is_file() => Scene::Stamp(Stamp::load(Path::new(name))?),
+            _ => bail!(
+                "Unknown scene '{}', expected one of {} or a stamp file",
+                name,
+                SCENE_NAMES.join(", ")
+            ),
+        })
+    }
+
+    /// Generated scenes adapt to any grid, stamps have to fit into it
+    pub fn check_fits(&self, width: u32, height: u32) -> anyhow::Result<()> {
+        if let Scene::Stamp(stamp) = self
+            && (stamp.width > width || stamp.height > height)
+        {
+            bail!(
+                "Scene stamp is {}x{} cells and doesn't fit into the {}x{} grid",
+                stamp.width,
+                stamp.height,
+                width,
+                height
+            );
+        }
+        Ok(())
+    }
+
+    /// Cells of the scene row by row, randomness is taken from a generator seeded with `seed`
+    pub fn cells(&self, width: u32, height: u32, seed: u64) -> Vec<u8> {
+        let mut rng = Rng::new(seed);
+        let mut cells = vec![0; width as usize * height as usize];
+
+        if let Scene::Stamp(stamp) = self {
+            let left = (width.saturating_sub(stamp.width) / 2) as usize;
+            let top = height.saturating_sub(stamp.height) as usize;
+            for (row, stamp_row) in stamp.cells.chunks(stamp.width as usize).enumerate() {
+                let start = (top + row) * width as usize + left;
+                if let Some(cells_row) = cells.get_mut(start..start + stamp_row.len()) {
+                    cells_row.copy_from_slice(stamp_row);
+                }
+            }
+            return cells;
+        }
+
+        for y in 0..height {
+            for x in 0..width {
+                cells[(y * width + x) as usize] = match self {
+                    Scene::Empty | Scene::Stamp(_) => 0,
+                    Scene::SandPile => {
+                        (y < height / 3 && x >= width * 3 / 8 && x < width * 5 / 8) as u8
+                    }
+                    Scene::Noise => [0, 0, 1, 2][rng.below(4) as usize],
+                    Scene::Ledges => {
+                        // Ledges reach in from the left and right side in turns
+                        let spacing = (height / 4).max(2);
+                        let ledge = y / spacing;
+                        let on_ledge = if ledge.is_multiple_of(2) {
+                            x < width * 3 / 5
+                        } else {
+                            x >= width * 2 / 5
+                        };
+                        if y % spacing == spacing - 1 && ledge > 0 && on_ledge {
+                            2
+                        } else if y < spacing / 2 {
+                            rng.below(2) as u8
+                        } else {
+                            0
+                        }
+                    }
+                };
+            }
+        }
+
+        cells
+    }
+}
@@ -0,0 +1,127 @@
+/// Most points a shape can have, limited by the size of the tool preview uniform in shader.wgsl
+pub const MAX_SHAPE_POINTS: usize = 32;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ShapeKind {
+    FilledRectangle,
+    OutlinedRectangle,
+    Line,
+    Polygon,
+}
+
+/// A shape drawn with one of the drawing tools, in grid coordinates.
+///
+/// Rectangles span from the first to the second point, lines connect them.
+/// How thick lines and outlines are is decided by the brush radius when drawing.
+///
+/// `contains` has to match `shape_contains` in shader.wgsl, which previews the shape.
+#[derive(Clone, Debug)]
+pub struct Shape {
+    pub kind: ShapeKind,
+    pub points: Vec<(i32, i32)>,
+}
+
+impl Shape {
+    pub fn new(kind: ShapeKind, start: (i32, i32)) -> Self {
+        // The last point follows the cursor until the shape is finished
+        Self {
+            kind,
+            points: vec![start, start],
+        }
+    }
+
+    /// Rectangle enclosing every cell the shape can contain, as (x0, y0, x1, y1)
+    pub fn bounds(&self, thickness: u32) -> (i32, i32, i32, i32) {
+        let margin = thickness as i32;
+        let x0 = self.points.iter().map(|point| point.0).min().unwrap_or(0);
+        let y0 = self.points.iter().map(|point| point.1).min().unwrap_or(0);
+        let x1 = self.points.iter().map(|point| point.0).max().unwrap_or(0);
+        let y1 = self.points.iter().map(|point| point.1).max().unwrap_or(0);
+
+        (x0 - margin, y0 - margin, x1 + margin, y1 + margin)
+    }
+
+    /// Whether the cell at (x, y) is part of the shape
+    pub fn contains(&self, x: i32, y: i32, thickness: u32) -> bool {
+        let thickness = thickness.max(1) as f32;
+        let point = (x as f32, y as f32);
+        let Some(&first) = self.points.first() else {
+            return false;
+        };
+        let last = self.points[self.points.len() - 1];
+
+        match self.kind {
+            ShapeKind::FilledRectangle | ShapeKind::OutlinedRectangle => {
+                let (x0, x1) = (first.0.min(last.0), first.0.max(last.0));
+                let (y0, y1) = (first.1.min(last.1), first.1.max(last.1));
+                if x < x0 || x > x1 || y < y0 || y > y1 {
+                    return false;
+                }
+
+                // The outline grows inwards
+                let border = thickness as i32;
+                self.kind == ShapeKind::FilledRectangle
+                    || x - x0 < border
+                    || x1 - x < border
+                    || y - y0 < border
+                    || y1 - y < border
+            }
+            ShapeKind::Line => {
+                distance_to_segment(point, to_f32(first), to_f32(last)) <= thickness / 2.0
+            }
+            ShapeKind::Polygon => {
+                // Even-odd rule, the edges are included so that unfinished polygons stay visible
+                let mut inside = false;
+                let mut previous = to_f32(last);
+                for &current in &self.points {
+                    let current = to_f32(current);
+                    if distance_to_segment(point, previous, current) <= 0.5 {
+                        return true;
+                    }
+
+                    if (current.1 > point.1) != (previous.1 > point.1)
+                        && point.0
+                            < (previous.0 - current.0) * (point.1 - current.1)
+                                / (previous.1 - current.1)
+                                + current.0
+                    {
+                        inside = !inside;
+                    }
+                    previous = current;
+                }
+                inside
+            }
+        }
+    }
+
+    /// Kind as used by the tool preview uniform in shader.wgsl
+    pub fn preview_kind(&self) -> u32 {
+        match self.kind {
+            ShapeKind::FilledRectangle => 2,
+            ShapeKind::OutlinedRectangle => 3,
+            ShapeKind::Line => 4,
+            ShapeKind::Polygon => 5,
+        }
+    }
+}
+
+fn to_f32(point: (i32, i32)) -> (f32, f32) {
+    (point.0 as f32, point.1 as f32)
+}
+
+fn distance_to_segment(point: (f32, f32), start: (f32, f32), end: (f32, f32)) -> f32 {
+    let (line_x, line_y) = (end.0 - start.0, end.1 - start.1);
+    let length_squared = line_x * line_x + line_y * line_y;
+
+    // Project the point onto the line to find the closest point on the segment
+    let t = if length_squared > 0.0 {
+        (((point.0 - start.0) * line_x + (point.1 - start.1) * line_y) / length_squared)
+            .clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+
+    let dx = point.0 - (start.0 + t * line_x);
+    let dy = point.1 - (start.1 + t * line_y);
+    (dx * dx + dy * dy).sqrt()
+}
@@ -1,39 +1,88 @@
-pub fn simulate_particles(particle_grid: &mut [u8], height: u32, width: u32) -> &[u8] {
+// Materials with rules, the other materials never move
+const AIR: u8 = 0;
+const SAND: u8 = 1;
+const STONE: u8 = 2;
+const WATER: u8 = 3;
+
+/// Advances the grid by one tick. Moving particles take their metadata along.
+/// Water that can't fall flows to the left first if `flow_left` is set and to the right first otherwise.
+pub fn simulate_particles(
+    particle_grid: &mut [u8],
+    metadata: &mut [u8],
+    height: u32,
+    width: u32,
+    flow_left: bool,
+) {
     let height = height as usize;
     let width = width as usize;
 
     // Goes from the bottom to the top and simulates each particle
-    for y in (0..height - 1).rev() {
+    for y in (0..height).rev() {
+        let has_row_below = y + 1 < height;
+        // Water that flowed to the right is not moved again in the same tick
+        let mut skip = false;
         for x in 0..width {
-            let idx: usize = (y * width + x) as usize;
-            // Sand
-            if particle_grid[idx] == 1 {
+            if std::mem::take(&mut skip) {
+                continue;
+            }
+
+            let idx: usize = y * width + x;
+            let particle = particle_grid[idx];
+            // Whether the particle can move into the cell, swapping places with what is in there
+            let sinks_into = |cell: u8| density(particle) > density(cell);
+
+            // Sand and water
+            if particle == SAND || particle == WATER {
                 // Try to move down
-                if particle_grid[idx + width] == 0 {
-                    particle_grid[idx] = 0;
-                    particle_grid[idx + width] = 1;
+                if has_row_below && sinks_into(particle_grid[idx + width]) {
+                    move_particle(particle_grid, metadata, idx, idx + width);
                 }
                 // Try to move down-right
-                else if x < width - 1 && particle_grid[idx + width + 1] == 0 {
-                    particle_grid[idx] = 0;
-                    particle_grid[idx + width + 1] = 1;
+                else if has_row_below
+                    && x < width - 1
+                    && sinks_into(particle_grid[idx + width + 1])
+                {
+                    move_particle(particle_grid, metadata, idx, idx + width + 1);
                 }
                 // Try to move down-left
-                else if x > 0 && particle_grid[idx + width - 1] == 0 {
-                    particle_grid[idx] = 0;
-                    particle_grid[idx + width - 1] = 1;
+                else if has_row_below && x > 0 && sinks_into(particle_grid[idx + width - 1]) {
+                    move_particle(particle_grid, metadata, idx, idx + width - 1);
+                }
+                // Water that can't fall flows sideways
+                else if particle == WATER {
+                    let can_flow_left = x > 0 && particle_grid[idx - 1] == AIR;
+                    let can_flow_right = x < width - 1 && particle_grid[idx + 1] == AIR;
+                    if can_flow_left && (flow_left || !can_flow_right) {
+                        move_particle(particle_grid, metadata, idx, idx - 1);
+                    } else if can_flow_right {
+                        move_particle(particle_grid, metadata, idx, idx + 1);
+                        skip = true;
+                    }
                 }
             }
             // Stone
-            else if particle_grid[idx] == 2 {
+            else if particle == STONE {
                 // Try to move down
-                if particle_grid[idx + width] == 0 {
-                    particle_grid[idx] = 0;
-                    particle_grid[idx + width] = 2;
+                if has_row_below && sinks_into(particle_grid[idx + width]) {
+                    move_particle(particle_grid, metadata, idx, idx + width);
                 }
             }
         }
     }
+}
+
+/// Heavier particles sink into lighter ones, materials without rules act like walls
+pub fn density(material: u8) -> u8 {
+    match material {
+        AIR => 0,
+        WATER => 1,
+        SAND | STONE => 2,
+        _ => u8::MAX,
+    }
+}
 
-    particle_grid
+/// Swaps the particles in the cells, which moves a particle into air or lets it sink into water
+fn move_particle(particle_grid: &mut [u8], metadata: &mut [u8], from: usize, to: usize) {
+    particle_grid.swap(from, to);
+    metadata.swap(from, to);
 }
@@ -0,0 +1,171 @@
+use super::material::MATERIALS;
+use crate::MAX_SAVED_STAMPS;
+use anyhow::{Context, bail};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// A rectangle of cells that can be pasted into the grid, e.g. the clipboard or a pre-built hourglass.
+///
+/// Stamp files are plain text with one row of cells per line and one digit per cell (the index of a
+/// registered material), lines starting with `#` are comments.
+#[derive(Clone, Debug)]
+pub struct Stamp {
+    pub width: u32,
+    pub height: u32,
+    /// Cells row by row
+    pub cells: Vec<u8>,
+}
+
+impl Stamp {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Reading stamp {} failed", path.display()))?;
+
+        let mut cells = Vec::new();
+        let mut width = None;
+        let mut height = 0;
+        for (line_number, line) in text.lines().enumerate() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if *width.get_or_insert(line.len()) != line.len() {
+                bail!(
+                    "Row in line {} of stamp {} has a different length than the first row",
+                    line_number + 1,
+                    path.display()
+                );
+            }
+            for c in line.chars() {
+                let Some(material) = c.to_digit(10) else {
+                    bail!(
+                        "Invalid cell '{}' in line {} of stamp {}",
+                        c,
+                        line_number + 1,
+                        path.display()
+                    );
+                };
+                if material as usize >= MATERIALS.len() {
+                    bail!(
+                        "Unknown material {} in line {} of stamp {}",
+                        material,
+                        line_number + 1,
+                        path.display()
+                    );
+                }
+                cells.push(material as u8);
+            }
+            height += 1;
+        }
+
+        let Some(width) = width else {
+            bail!("Stamp {} is empty", path.display());
+        };
+        Ok(Self {
+            width: width as u32,
+            height,
+            cells,
+        })
+    }
+
+    /// Saves the stamp as the first free `stamp-N.stamp` file of the directory and returns its path.
+    /// The file is only created if it doesn't exist yet, so saves in quick succession never overwrite
+    /// each other.
+    pub fn save_new(&self, directory: &Path) -> anyhow::Result<PathBuf> {
+        let mut text = String::with_capacity((self.width as usize + 1) * self.height as usize);
+        for row in self.cells.chunks(self.width as usize) {
+            text.extend(row.iter().map(|&cell| char::from(b'0' + cell)));
+            text.push('\n');
+        }
+
+        std::fs::create_dir_all(directory)?;
+        for number in 1..=MAX_SAVED_STAMPS {
+            let path = directory.join(format!("stamp-{}.stamp", number));
+            let mut file = match std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(file) => file,
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(e) => {
+                    return Err(e)
+                        .with_context(|| format!("Creating stamp {} failed", path.display()));
+                }
+            };
+            file.write_all(text.as_bytes())
+                .with_context(|| format!("Writing stamp {} failed", path.display()))?;
+            return Ok(path);
+        }
+
+        bail!(
+            "All {} stamp file names in {} are taken",
+            MAX_SAVED_STAMPS,
+            directory.display()
+        )
+    }
+
+    pub fn rotate_clockwise(&mut self) {
+        let (width, height) = (self.width as usize, self.height as usize);
+        let mut cells = vec![0; self.cells.len()];
+        for y in 0..height {
+            for x in 0..width {
+                // The last row becomes the first column
+                cells[x * height + (height - 1 - y)] = self.cells[y * width + x];
+            }
+        }
+
+        self.cells = cells;
+        std::mem::swap(&mut self.width, &mut self.height);
+    }
+
+    pub fn flip_horizontally(&mut self) {
+        for row in self.cells.chunks_mut(self.width as usize) {
+            row.reverse();
+        }
+    }
+
+    pub fn flip_vertically(&mut self) {
+        let width = self.width as usize;
+        let height = self.height as usize;
+        for y in 0..height / 2 {
+            let (top, bottom) = self.cells.split_at_mut((height - 1 - y) * width);
+            top[y * width..(y + 1) * width].swap_with_slice(&mut bottom[..width]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_unregistered_materials() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!("unknown-{}.stamp", std::process::id()));
+        std::fs::write(&path, format!("# comment\n01\n{}0\n", MATERIALS.len()))?;
+        let error = Stamp::load(&path).unwrap_err().to_string();
+        std::fs::remove_file(&path)?;
+
+        assert!(error.contains(&format!("Unknown material {} in line 3", MATERIALS.len())));
+        Ok(())
+    }
+
+    #[test]
+    fn saves_never_overwrite_each_other() -> anyhow::Result<()> {
+        let directory = std::env::temp_dir().join(format!("stamps-{}", std::process::id()));
+        let stamp = Stamp {
+            width: 2,
+            height: 1,
+            cells: vec![1, 3],
+        };
+        let first = stamp.save_new(&directory)?;
+        let second = stamp.save_new(&directory)?;
+        let loaded = Stamp::load(&second)?;
+        std::fs::remove_dir_all(&directory)?;
+
+        assert_ne!(first, second);
+        assert_eq!(loaded.cells, stamp.cells);
+        Ok(())
+    }
+}
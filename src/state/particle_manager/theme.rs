@@ -0,0 +1,74 @@
+/// Color scheme of the app. Every material defines its color per theme, see `Material::colors`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Theme {
+    #[default]
+    Light,
+    Dark,
+    HighContrast,
+    /// Colors that stay distinguishable with the common kinds of color blindness (Okabe-Ito)
+    Colorblind,
+}
+
+impl Theme {
+    pub const ALL: [Theme; 4] = [
+        Theme::Light,
+        Theme::Dark,
+        Theme::HighContrast,
+        Theme::Colorblind,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Theme::Light => "light",
+            Theme::Dark => "dark",
+            Theme::HighContrast => "high-contrast",
+            Theme::Colorblind => "colorblind",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|theme| theme.name() == name)
+    }
+
+    pub fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+
+    /// Color of the window around the grid
+    pub fn background(self) -> [f32; 3] {
+        match self {
+            Theme::Light | Theme::Colorblind => [0.0, 0.0, 0.0],
+            Theme::Dark => [0.2, 0.2, 0.22],
+            Theme::HighContrast => [0.4, 0.4, 0.4],
+        }
+    }
+
+    /// Color of the HUD text and the border of the selected material in the palette bar
+    pub fn text(self) -> [f32; 3] {
+        match self {
+            Theme::Light | Theme::Colorblind => [0.0, 0.0, 0.0],
+            Theme::Dark => [0.9, 0.9, 0.9],
+            Theme::HighContrast => [1.0, 1.0, 1.0],
+        }
+    }
+
+    /// Color of the translucent box behind the HUD text
+    pub fn panel(self) -> [f32; 3] {
+        match self {
+            Theme::Light | Theme::Colorblind => [1.0, 1.0, 1.0],
+            Theme::Dark => [0.1, 0.1, 0.12],
+            Theme::HighContrast => [0.0, 0.0, 0.0],
+        }
+    }
+
+    /// Layout of the theme uniform in shader.wgsl: background, text and panel color
+    pub fn to_uniform(self) -> [f32; 12] {
+        let [background, text, panel] =
+            [self.background(), self.text(), self.panel()].map(|[r, g, b]| [r, g, b, 1.0]);
+        let mut uniform = [0.0; 12];
+        uniform[..4].copy_from_slice(&background);
+        uniform[4..8].copy_from_slice(&text);
+        uniform[8..].copy_from_slice(&panel);
+        uniform
+    }
+}
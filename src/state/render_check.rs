@@ -0,0 +1,74 @@
+use super::gpu_context::{OffscreenContext, test_device};
+use super::particle_manager::{MATERIALS, Theme};
+use super::render_offscreen;
+use anyhow::bail;
+
+// Size of the offscreen texture, the grid is centered in it below the palette bar
+const RENDER_WIDTH: u32 = 512;
+const RENDER_HEIGHT: u32 = 128;
+// Largest difference allowed per color channel. The palette texture stores colors and variations
+// with 8 bits, so the shader can be a few steps off from colors computed in full precision.
+const MAX_CHANNEL_ERROR: u8 = 3;
+
+/// Renders every material with every metadata value offscreen in all themes and compares the
+/// pixels with the colors the materials define, computed on the CPU
+#[test]
+fn rendered_colors_match_the_materials() -> anyhow::Result<()> {
+    let Some((device, queue)) = test_device() else {
+        return Ok(());
+    };
+    let context = OffscreenContext::new(device, queue, RENDER_WIDTH, RENDER_HEIGHT);
+
+    // One row per material, one column per metadata value
+    let (width, height) = (u8::MAX as u32 + 1, MATERIALS.len() as u32);
+    let particles: Vec<u8> = (0..height as u8)
+        .flat_map(|material| std::iter::repeat_n(material, width as usize))
+        .collect();
+    let metadata: Vec<u8> = (0..height).flat_map(|_| 0..=u8::MAX).collect();
+
+    for theme in Theme::ALL {
+        let viewport =
+            render_offscreen(&context, particles.clone(), &metadata, width, height, theme);
+        let pixels = context.read_pixels()?;
+
+        // The pixel in the middle of every cell
+        for (index, (&material, &metadata)) in particles.iter().zip(&metadata).enumerate() {
+            let (x, y) = (index as u32 % width, index as u32 / width);
+            let pixel_x = (viewport.offset_x + (x as f64 + 0.5) * viewport.scale) as usize;
+            let pixel_y = (viewport.offset_y + (y as f64 + 0.5) * viewport.scale) as usize;
+            let start = (pixel_y * RENDER_WIDTH as usize + pixel_x) * 4;
+            let rendered = &pixels[start..start + 3];
+
+            let expected = MATERIALS[material as usize]
+                .particle_color(theme, metadata)
+                .map(to_srgb_byte);
+            if rendered
+                .iter()
+                .zip(expected)
+                .any(|(&rendered, expected)| rendered.abs_diff(expected) > MAX_CHANNEL_ERROR)
+            {
+                bail!(
+                    "{} with metadata {} is rendered as {:?} instead of {:?} in the {} theme",
+                    MATERIALS[material as usize].name,
+                    metadata,
+                    rendered,
+                    expected,
+                    theme.name()
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Encodes a linear color channel the way the sRGB render texture stores it
+fn to_srgb_byte(channel: f32) -> u8 {
+    let channel = channel.clamp(0.0, 1.0);
+    let encoded = if channel <= 0.0031308 {
+        channel * 12.92
+    } else {
+        1.055 * channel.powf(1.0 / 2.4) - 0.055
+    };
+    (encoded * 255.0).round() as u8
+}
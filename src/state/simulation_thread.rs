@@ -0,0 +1,291 @@
+use super::particle_manager::{Brush, ParticleManager};
+use crate::STATS_INTERVAL_MS;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Messages from the event loop to the simulation thread
+pub enum Command {
+    /// Edit applied to the particle manager between two ticks
+    Edit(Box<dyn FnOnce(&mut ParticleManager) + Send>),
+    SetUpdateInterval(Duration),
+    SetPaused(bool),
+    /// Runs a single tick, used while paused
+    Step,
+    Shutdown,
+}
+
+/// State of the simulation published after every change
+#[derive(Default)]
+pub struct Snapshot {
+    /// Empty if the simulation backend renders directly
+    pub particle_grid: Vec<u8>,
+    /// Metadata of every cell, empty along with the grid
+    pub particle_metadata: Vec<u8>,
+    /// Direction every cell's particle moved in and which chunks changed during the last tick,
+    /// empty unless a debug overlay shows them
+    pub motion: Vec<u8>,
+    pub chunk_activity: Vec<u8>,
+    /// Width and height of the grid, which changes when it is resized
+    pub grid_size: (u32, u32),
+    pub selected_material: u8,
+    pub brush: Brush,
+    /// Width and height of the clipboard, (0, 0) if it is empty
+    pub clipboard_size: (u32, u32),
+    pub stats: SimulationStats,
+    generation: u64,
+}
+
+/// Measurements of the simulation, updated every STATS_INTERVAL_MS
+#[derive(Default, Clone)]
+pub struct SimulationStats {
+    pub ticks_per_second: f32,
+    /// Average time simulating a tick took
+    pub tick_time: Duration,
+    /// Number of cells of each material, indexed by material
+    pub particle_counts: Vec<u32>,
+}
+
+/// Runs the simulation on its own thread, so rendering and input handling are never blocked by it.
+///
+/// Finished grids are published through a double buffer: the thread fills its back snapshot
+/// and swaps it with the shared front snapshot. The event loop in turn swaps the front snapshot
+/// with its own latest one, so neither side holds the lock for more than a swap.
+pub struct SimulationThread {
+    sender: Sender<Command>,
+    front: Arc<Mutex<Snapshot>>,
+    latest: Snapshot,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl SimulationThread {
+    pub fn spawn(
+        particle_manager: ParticleManager,
+        update_interval: Duration,
+        max_steps_per_frame: u32,
+    ) -> anyhow::Result<Self> {
+        let (sender, receiver) = mpsc::channel();
+        let front = Arc::new(Mutex::new(Snapshot::default()));
+
+        let worker = Worker {
+            particle_manager,
+            receiver,
+            front: front.clone(),
+            back: Snapshot::default(),
+            stats: SimulationStats::default(),
+            paused: false,
+            update_interval,
+            max_steps_per_frame,
+        };
+        let handle = std::thread::Builder::new()
+            .name("simulation".into())
+            .spawn(move || worker.run())?;
+
+        Ok(Self {
+            sender,
+            front,
+            latest: Snapshot::default(),
+            handle: Some(handle),
+        })
+    }
+
+    /// Queues an edit, which is applied before the next tick
+    pub fn edit(&self, edit: impl FnOnce(&mut ParticleManager) + Send + 'static) {
+        self.send(Command::Edit(Box::new(edit)));
+    }
+
+    pub fn send(&self, command: Command) {
+        if self.sender.send(command).is_err() {
+            log::error!("Simulation thread is not running anymore");
+        }
+    }
+
+    /// Returns the latest snapshot if it changed since the last call
+    pub fn new_snapshot(&mut self) -> Option<&Snapshot> {
+        let mut front = match self.front.lock() {
+            Ok(front) => front,
+            Err(e) => {
+                log::error!("Simulation thread panicked: {}", e);
+                return None;
+            }
+        };
+
+        if front.generation == self.latest.generation {
+            return None;
+        }
+        // The front keeps the generation, so the next one published is newer than the latest snapshot
+        std::mem::swap(&mut *front, &mut self.latest);
+        front.generation = self.latest.generation;
+        drop(front);
+
+        Some(&self.latest)
+    }
+}
+
+impl Drop for SimulationThread {
+    fn drop(&mut self) {
+        self.send(Command::Shutdown);
+
+        if let Some(handle) = self.handle.take()
+            && handle.join().is_err()
+        {
+            log::error!("Simulation thread panicked");
+        }
+    }
+}
+
+struct Worker {
+    particle_manager: ParticleManager,
+    receiver: Receiver<Command>,
+    front: Arc<Mutex<Snapshot>>,
+    back: Snapshot,
+    stats: SimulationStats,
+
+    paused: bool,
+    update_interval: Duration,
+    max_steps_per_frame: u32,
+}
+
+impl Worker {
+    fn run(mut self) {
+        // Fixed timestep: time not simulated yet is collected in the accumulator
+        // and consumed in steps of update_interval
+        let mut last_frame = Instant::now();
+        let mut accumulator = Duration::ZERO;
+
+        // Ticks and the time spent on them since the stats were last updated
+        let mut last_stats_update = Instant::now();
+        let mut ticks = 0;
+        let mut tick_time = Duration::ZERO;
+        self.stats.particle_counts = self.particle_manager.particle_counts();
+
+        // Publish the initial state
+        let mut changed = true;
+
+        loop {
+            if changed {
+                self.publish();
+            }
+            changed = false;
+
+            // Sleep until the next tick is due, or indefinitely while paused.
+            // Incoming commands wake the thread up.
+            let mut command = if self.paused {
+                match self.receiver.recv() {
+                    Ok(command) => Some(command),
+                    Err(_) => return,
+                }
+            } else {
+                let until_next_tick = self
+                    .update_interval
+                    .saturating_sub(accumulator + last_frame.elapsed());
+                match self.receiver.recv_timeout(until_next_tick) {
+                    Ok(command) => Some(command),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            };
+
+            // Apply all queued commands before simulating
+            while let Some(current) = command {
+                match current {
+                    Command::Edit(edit) => edit(&mut self.particle_manager),
+                    Command::SetUpdateInterval(update_interval) => {
+                        self.update_interval = update_interval
+                    }
+                    Command::SetPaused(paused) => self.paused = paused,
+                    Command::Step => {
+                        let tick_start = Instant::now();
+                        self.particle_manager.simulate_particles();
+                        tick_time += tick_start.elapsed();
+                        ticks += 1;
+                    }
+                    Command::Shutdown => return,
+                }
+                changed = true;
+                command = self.receiver.try_recv().ok();
+            }
+
+            let now = Instant::now();
+            accumulator += now.duration_since(last_frame);
+            last_frame = now;
+
+            // Counting particles reads the whole grid, so it is done once per interval.
+            // While paused only edits change the counts, so they are counted right away.
+            let since_stats_update = now.duration_since(last_stats_update);
+            if since_stats_update >= Duration::from_millis(STATS_INTERVAL_MS) {
+                self.stats.ticks_per_second = ticks as f32 / since_stats_update.as_secs_f32();
+                self.stats.tick_time = tick_time.checked_div(ticks).unwrap_or_default();
+                self.stats.particle_counts = self.particle_manager.particle_counts();
+                last_stats_update = now;
+                ticks = 0;
+                tick_time = Duration::ZERO;
+                changed = true;
+            } else if self.paused && changed {
+                self.stats.particle_counts = self.particle_manager.particle_counts();
+            }
+
+            // Time spent paused is not caught up on afterwards
+            if self.paused {
+                accumulator = Duration::ZERO;
+                continue;
+            }
+
+            let mut steps = 0;
+            while accumulator >= self.update_interval && steps < self.max_steps_per_frame {
+                let tick_start = Instant::now();
+                self.particle_manager.simulate_particles();
+                tick_time += tick_start.elapsed();
+                accumulator -= self.update_interval;
+                steps += 1;
+            }
+            ticks += steps;
+
+            // If the simulation can't keep up, drop the remaining backlog instead of falling further behind
+            if steps == self.max_steps_per_frame && accumulator >= self.update_interval {
+                log::debug!("Simulation is falling behind, skipping {:?}", accumulator);
+                accumulator = Duration::ZERO;
+            }
+
+            changed |= steps > 0;
+        }
+    }
+
+    /// Fills the back snapshot and swaps it with the front snapshot
+    fn publish(&mut self) {
+        self.back.particle_grid.clear();
+        self.back.particle_metadata.clear();
+        if !self.particle_manager.renders_directly() {
+            self.back
+                .particle_grid
+                .extend_from_slice(self.particle_manager.particle_grid());
+            self.back
+                .particle_metadata
+                .extend_from_slice(self.particle_manager.particle_metadata());
+        }
+        self.back.motion.clear();
+        self.back.chunk_activity.clear();
+        if let Some(motion) = self.particle_manager.motion() {
+            self.back.motion.extend_from_slice(motion.motion());
+            self.back
+                .chunk_activity
+                .extend_from_slice(motion.chunk_activity());
+        }
+        self.back.grid_size = self.particle_manager.size();
+        self.back.selected_material = self.particle_manager.selected_material();
+        self.back.brush = self.particle_manager.brush();
+        self.back.clipboard_size = self.particle_manager.clipboard_size();
+        self.back.stats.clone_from(&self.stats);
+
+        let mut front = match self.front.lock() {
+            Ok(front) => front,
+            Err(e) => {
+                log::error!("Publishing simulation snapshot failed: {}", e);
+                return;
+            }
+        };
+        self.back.generation = front.generation + 1;
+        std::mem::swap(&mut *front, &mut self.back);
+    }
+}
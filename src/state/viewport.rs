@@ -0,0 +1,45 @@
+/// Where the grid is drawn in the window: scaled by a whole number where possible so all cells are
+/// equally large and square, and centered with bars filling the remaining space.
+///
+/// fs_main in shader.wgsl draws with the same transform, so cursor positions map to the cell under them.
+#[derive(Clone, Copy, Debug)]
+pub struct Viewport {
+    /// Top left corner of the grid in pixels
+    pub offset_x: f64,
+    pub offset_y: f64,
+    /// Pixels per cell
+    pub scale: f64,
+}
+
+impl Viewport {
+    pub fn new(window_width: u32, window_height: u32, grid_width: u32, grid_height: u32) -> Self {
+        let fit = (window_width as f64 / grid_width as f64)
+            .min(window_height as f64 / grid_height as f64);
+        // Windows smaller than the grid can't use whole numbers, the grid is shrunk to fit instead
+        let scale = if fit >= 1.0 { fit.floor() } else { fit };
+
+        Self {
+            offset_x: ((window_width as f64 - grid_width as f64 * scale) / 2.0).floor(),
+            offset_y: ((window_height as f64 - grid_height as f64 * scale) / 2.0).floor(),
+            scale,
+        }
+    }
+
+    /// Converts a position in the window in pixels to a position inside the viewport, in cells of the unzoomed grid
+    pub fn window_to_view(&self, x: f64, y: f64) -> (f64, f64) {
+        (
+            (x - self.offset_x) / self.scale,
+            (y - self.offset_y) / self.scale,
+        )
+    }
+
+    /// Layout of the viewport uniform in shader.wgsl: offset, scale, unused
+    pub fn to_uniform(self) -> [f32; 4] {
+        [
+            self.offset_x as f32,
+            self.offset_y as f32,
+            self.scale as f32,
+            0.0,
+        ]
+    }
+}